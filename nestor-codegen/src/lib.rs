@@ -3,20 +3,126 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
-use syn::AttributeArgs;
+use syn::{Expr, Ident, Lit, LitStr, Token};
 
 use quote::{quote, quote_spanned};
-use syn::{parse_macro_input, ItemFn, NestedMeta};
+use syn::{parse_macro_input, ItemFn};
 
 const COMMAND_PREFIX: &'static str = "nestor_command_handler_";
 
+enum CommandArg {
+    Route(LitStr),
+    Named(Ident, Box<Expr>),
+}
+
+impl Parse for CommandArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            return Ok(CommandArg::Route(input.parse()?));
+        }
+
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        Ok(CommandArg::Named(name, input.parse()?))
+    }
+}
+
+struct CommandArgs {
+    route: Option<LitStr>,
+    aliases: Vec<LitStr>,
+    rank: Option<Box<Expr>>,
+    timeout: Option<Box<Expr>>,
+}
+
+impl Parse for CommandArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = CommandArgs {
+            route: None,
+            aliases: Vec::new(),
+            rank: None,
+            timeout: None,
+        };
+
+        for arg in Punctuated::<CommandArg, Token![,]>::parse_terminated(input)? {
+            match arg {
+                CommandArg::Route(route) if args.route.is_none() => args.route = Some(route),
+                CommandArg::Route(route) => {
+                    return Err(syn::Error::new(route.span(), "route specified twice"))
+                }
+                CommandArg::Named(name, value) if name == "aliases" => {
+                    args.aliases = parse_str_array(&value)?
+                }
+                CommandArg::Named(name, value) if name == "rank" => args.rank = Some(value),
+                CommandArg::Named(name, value) if name == "timeout" => args.timeout = Some(value),
+                CommandArg::Named(name, _) => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        format!("unknown command attribute `{}`", name),
+                    ))
+                }
+            }
+        }
+
+        if args.route.is_none() && !args.aliases.is_empty() {
+            return Err(syn::Error::new(
+                input.span(),
+                "aliases require the command to have a route",
+            ));
+        }
+
+        if args.route.is_some() && args.rank.is_some() {
+            return Err(syn::Error::new(
+                input.span(),
+                "rank only applies to default commands without a route",
+            ));
+        }
+
+        Ok(args)
+    }
+}
+
+fn parse_str_array(expr: &Expr) -> syn::Result<Vec<LitStr>> {
+    let array = match expr {
+        Expr::Array(array) => array,
+        _ => {
+            return Err(syn::Error::new(
+                expr.span(),
+                "expected an array of string literals",
+            ))
+        }
+    };
+
+    array
+        .elems
+        .iter()
+        .map(|elem| match elem {
+            Expr::Lit(lit) => match &lit.lit {
+                Lit::Str(s) => Ok(s.clone()),
+                _ => Err(syn::Error::new(lit.span(), "expected a string literal")),
+            },
+            _ => Err(syn::Error::new(elem.span(), "expected a string literal")),
+        })
+        .collect()
+}
+
 #[proc_macro_attribute]
 pub fn command(macro_args: TokenStream, item: TokenStream) -> TokenStream {
-    let macro_args = parse_macro_input!(macro_args as AttributeArgs);
-    let route = match macro_args.get(0) {
-        Some(NestedMeta::Lit(lit)) => quote! { Some(#lit) },
-        _ => quote! { None },
+    let macro_args = parse_macro_input!(macro_args as CommandArgs);
+    let route = match &macro_args.route {
+        Some(lit) => quote! { Some(#lit) },
+        None => quote! { None },
+    };
+    let aliases = &macro_args.aliases;
+    let rank = match &macro_args.rank {
+        Some(rank) => quote! { #rank },
+        None => quote! { 0 },
+    };
+    let timeout = match &macro_args.timeout {
+        Some(seconds) => quote! { Some(std::time::Duration::from_secs(#seconds)) },
+        None => quote! { None },
     };
 
     let item = parse_macro_input!(item as ItemFn);
@@ -30,9 +136,23 @@ pub fn command(macro_args: TokenStream, item: TokenStream) -> TokenStream {
         .sig
         .inputs
         .iter()
-        .map(|input| {
+        .enumerate()
+        .map(|(i, input)| syn::Ident::new(&format!("__nestor_arg_{}", i), input.span()))
+        .collect();
+
+    let extractors: Vec<_> = item
+        .sig
+        .inputs
+        .iter()
+        .zip(&args)
+        .map(|(input, arg)| {
             let span = input.span();
-            quote_spanned!(span=> nestor::request::FromRequest::from_request(request)?)
+            quote_spanned! {span=>
+                let #arg = match nestor::request::FromRequestAsync::from_request(request).await {
+                    Ok(value) => value,
+                    Err(err) => return nestor::response::Outcome::Failure(std::convert::Into::into(err)),
+                };
+            }
         })
         .collect();
 
@@ -41,19 +161,18 @@ pub fn command(macro_args: TokenStream, item: TokenStream) -> TokenStream {
         syn::ReturnType::Type(_, ty) => (ty.span(), quote! {#ty}),
     };
 
-    let function_call = if let Some(_) = item.sig.asyncness {
-        quote_spanned! {span=> {
-            let fut = #fn_name(#(#args),*);
-            async {
-                let val = fut.await;
-                <#ty as IntoOutcome>::into_outcome(val)
-            }
-        }}
+    let call = if let Some(_) = item.sig.asyncness {
+        quote_spanned! {span=> #fn_name(#(#args),*).await }
     } else {
-        quote_spanned! {span => {
-            let res = <#ty as IntoOutcome>::into_outcome(#fn_name(#(#args),*));
-            async {res}
-        }}
+        quote_spanned! {span=> #fn_name(#(#args),*) }
+    };
+
+    let function_call = quote_spanned! {span=>
+        async move {
+            #(#extractors)*
+            let val = #call;
+            <#ty as IntoOutcome>::into_outcome(val)
+        }
     };
 
     let result = quote! {
@@ -67,6 +186,18 @@ pub fn command(macro_args: TokenStream, item: TokenStream) -> TokenStream {
                 #route
             }
 
+            fn aliases(&self) -> &'static [&'static str] {
+                &[#(#aliases),*]
+            }
+
+            fn rank(&self) -> isize {
+                #rank
+            }
+
+            fn timeout(&self) -> Option<std::time::Duration> {
+                #timeout
+            }
+
             fn handle<'a, 'r>(
                 &'a self,
                 request: &'a nestor::request::Request<'r>,
@@ -84,3 +215,24 @@ pub fn command(macro_args: TokenStream, item: TokenStream) -> TokenStream {
 
     result.into()
 }
+
+#[proc_macro]
+pub fn routes(input: TokenStream) -> TokenStream {
+    let paths = parse_macro_input!(input with Punctuated::<syn::Path, Token![,]>::parse_terminated);
+
+    let handlers = paths.into_iter().map(|mut path| {
+        if let Some(segment) = path.segments.last_mut() {
+            segment.ident = syn::Ident::new(
+                &format!("{}{}", COMMAND_PREFIX, segment.ident),
+                segment.ident.span(),
+            );
+        }
+
+        quote! { Box::new(#path) as Box<dyn nestor::handler::CommandHandler> }
+    });
+
+    (quote! {
+        vec![#(#handlers),*]
+    })
+    .into()
+}
@@ -0,0 +1,198 @@
+use crate::config::{Ircv3Settings, Sasl};
+
+use anyhow::Result;
+use irc::client::prelude::*;
+use irc::proto::CapSubCommand;
+
+const AUTHENTICATE_CHUNK: usize = 400;
+
+pub(crate) struct Negotiation {
+    capabilities: Vec<String>,
+    sasl: Option<Sasl>,
+    nickname: String,
+    available: Vec<String>,
+    ended: bool,
+    registered: bool,
+}
+
+impl Negotiation {
+    pub(crate) fn start(
+        client: &Client,
+        irc_config: &Config,
+        settings: &Ircv3Settings,
+    ) -> Result<Self> {
+        client.send(Command::CAP(
+            None,
+            CapSubCommand::LS,
+            Some("302".into()),
+            None,
+        ))?;
+        if !irc_config.password().is_empty() {
+            client.send(Command::PASS(irc_config.password().into()))?;
+        }
+        client.send(Command::NICK(irc_config.nickname()?.into()))?;
+        client.send(Command::USER(
+            irc_config.username().into(),
+            "0".into(),
+            irc_config.real_name().into(),
+        ))?;
+
+        Ok(Negotiation {
+            capabilities: settings.capabilities.clone(),
+            sasl: settings.sasl.clone(),
+            nickname: irc_config.nickname()?.into(),
+            available: Vec::new(),
+            ended: false,
+            registered: false,
+        })
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.ended && self.registered
+    }
+
+    pub(crate) fn handle(&mut self, client: &Client, message: &Message) -> Result<()> {
+        match &message.command {
+            Command::CAP(_, CapSubCommand::LS, Some(marker), Some(list)) if marker == "*" => {
+                self.available.extend(capability_names(list));
+            }
+            Command::CAP(_, CapSubCommand::LS, Some(list), None) => {
+                self.available.extend(capability_names(list));
+                self.request(client)?;
+            }
+            Command::CAP(_, CapSubCommand::ACK, first, second) => {
+                let acked = second
+                    .as_ref()
+                    .or(first.as_ref())
+                    .map(|list| capability_names(list))
+                    .unwrap_or_default();
+                match &self.sasl {
+                    Some(sasl) if acked.iter().any(|cap| cap == "sasl") => {
+                        let mechanism = match sasl {
+                            Sasl::Plain { .. } => "PLAIN",
+                            Sasl::External => "EXTERNAL",
+                        };
+                        client.send(Command::AUTHENTICATE(mechanism.into()))?;
+                    }
+                    _ => self.end(client)?,
+                }
+            }
+            Command::CAP(_, CapSubCommand::NAK, ..) => {
+                println!("server refused requested capabilities");
+                self.end(client)?;
+            }
+            Command::AUTHENTICATE(data) if data == "+" => self.authenticate(client)?,
+            Command::Response(Response::RPL_SASLSUCCESS, _) => self.end(client)?,
+            Command::Response(response @ Response::ERR_NICKLOCKED, _)
+            | Command::Response(response @ Response::ERR_SASLFAIL, _)
+            | Command::Response(response @ Response::ERR_SASLTOOLONG, _)
+            | Command::Response(response @ Response::ERR_SASLABORTED, _)
+            | Command::Response(response @ Response::ERR_SASLALREADY, _) => {
+                println!("SASL authentication failed: {:?}", response);
+                self.end(client)?;
+            }
+            Command::Response(Response::RPL_WELCOME, _) => {
+                self.ended = true;
+                self.registered = true;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn request(&mut self, client: &Client) -> Result<()> {
+        let sasl = self.sasl.as_ref().map(|_| "sasl".to_string());
+        let wanted: Vec<String> = self
+            .capabilities
+            .iter()
+            .cloned()
+            .chain(sasl)
+            .filter(|cap| self.available.contains(cap))
+            .collect();
+
+        if wanted.is_empty() {
+            return self.end(client);
+        }
+
+        client.send(Command::CAP(
+            None,
+            CapSubCommand::REQ,
+            None,
+            Some(wanted.join(" ")),
+        ))?;
+        Ok(())
+    }
+
+    fn authenticate(&mut self, client: &Client) -> Result<()> {
+        match &self.sasl {
+            Some(Sasl::Plain { username, password }) => {
+                let username = username.as_deref().unwrap_or(&self.nickname);
+                for chunk in sasl_plain_payload(username, password) {
+                    client.send(Command::AUTHENTICATE(chunk))?;
+                }
+            }
+            Some(Sasl::External) => client.send(Command::AUTHENTICATE("+".into()))?,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self, client: &Client) -> Result<()> {
+        if !self.ended {
+            client.send(Command::CAP(None, CapSubCommand::END, None, None))?;
+            self.ended = true;
+        }
+
+        Ok(())
+    }
+}
+
+fn capability_names(list: &str) -> Vec<String> {
+    list.split_whitespace()
+        .map(|cap| cap.split('=').next().unwrap_or(cap).to_string())
+        .collect()
+}
+
+fn sasl_plain_payload(username: &str, password: &str) -> Vec<String> {
+    let payload = base64::encode(format!("\0{}\0{}", username, password));
+    let mut chunks: Vec<String> = payload
+        .as_bytes()
+        .chunks(AUTHENTICATE_CHUNK)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect();
+
+    if payload.len() % AUTHENTICATE_CHUNK == 0 {
+        chunks.push("+".into());
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::{capability_names, sasl_plain_payload};
+
+    #[test]
+    fn capability_values_are_stripped() {
+        assert_eq!(
+            capability_names("sasl=PLAIN,EXTERNAL server-time message-tags"),
+            ["sasl", "server-time", "message-tags"]
+        );
+    }
+
+    #[test]
+    fn plain_payload() {
+        assert_eq!(sasl_plain_payload("bot", "hunter2"), ["AGJvdABodW50ZXIy"]);
+    }
+
+    #[test]
+    fn long_plain_payload_is_chunked() {
+        let password = "x".repeat(300);
+        let chunks = sasl_plain_payload("bot", &password);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), 400);
+    }
+}
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use irc::client::data::Config as IrcConfig;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -18,13 +23,668 @@ pub struct NestorSettings {
     pub blacklisted_users: Vec<String>,
     pub command_indicator: Vec<String>,
     pub alias_depth: u32,
+    #[serde(default)]
+    pub case_insensitive_commands: bool,
+    #[serde(default)]
+    pub casemapping: CaseMapping,
+    #[serde(default)]
+    pub reply_mode: ReplyMode,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+    #[serde(default)]
+    pub command_timeout: Option<u64>,
+    #[serde(default)]
+    pub command_timeouts: HashMap<String, u64>,
+    #[serde(default)]
+    pub channel_settings: HashMap<String, ChannelOverrides>,
+    #[serde(default)]
+    pub ircv3: Option<Ircv3Settings>,
+    #[serde(default)]
+    pub outbound: OutboundSettings,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ChannelOverrides {
+    pub command_indicator: Option<Vec<String>>,
+    pub reply_mode: Option<ReplyMode>,
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub enabled_commands: Vec<String>,
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplyMode {
+    Default,
+    Say,
+    Notice,
+}
+
+// Written out because `#[default]` on enum variants needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for ReplyMode {
+    fn default() -> Self {
+        ReplyMode::Default
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RateLimit {
+    pub commands: u32,
+    pub per_seconds: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(default)]
+pub struct OutboundSettings {
+    pub burst: u32,
+    pub interval_millis: u64,
+    pub stale_after_seconds: u64,
+}
+
+impl Default for OutboundSettings {
+    fn default() -> Self {
+        OutboundSettings {
+            burst: 4,
+            interval_millis: 1000,
+            stale_after_seconds: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Ircv3Settings {
+    #[serde(default = "default_capabilities")]
+    pub capabilities: Vec<String>,
+    pub sasl: Option<Sasl>,
+    #[serde(default)]
+    pub threaded_replies: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(tag = "mechanism", rename_all = "kebab-case")]
+pub enum Sasl {
+    Plain {
+        username: Option<String>,
+        password: String,
+    },
+    External,
+}
+
+fn default_capabilities() -> Vec<String> {
+    vec![
+        "message-tags".into(),
+        "server-time".into(),
+        "account-tag".into(),
+        "echo-message".into(),
+        "labeled-response".into(),
+    ]
+}
+
+pub struct ChannelSettings<'c> {
+    pub channel: Option<&'c str>,
+    pub command_indicator: &'c [String],
+    pub reply_mode: ReplyMode,
+    pub rate_limit: Option<RateLimit>,
+    global: &'c NestorSettings,
+    overrides: Option<&'c ChannelOverrides>,
+}
+
+impl<'c> ChannelSettings<'c> {
+    pub fn is_enabled(&self, command: &str) -> bool {
+        let casemapping = self.global.casemapping;
+        let listed = |commands: &[String]| commands.iter().any(|c| casemapping.eq(c, command));
+
+        match self.overrides {
+            Some(overrides) if listed(&overrides.disabled_commands) => false,
+            Some(overrides) if listed(&overrides.enabled_commands) => true,
+            _ => !listed(&self.global.disabled_commands),
+        }
+    }
+}
+
+impl NestorSettings {
+    pub fn for_channel<'c>(&'c self, channel: Option<&'c str>) -> ChannelSettings<'c> {
+        let overrides = channel.and_then(|channel| {
+            self.channel_settings
+                .iter()
+                .find(|(name, _)| self.casemapping.eq(name, channel))
+                .map(|(_, overrides)| overrides)
+        });
+
+        ChannelSettings {
+            channel,
+            command_indicator: overrides
+                .and_then(|o| o.command_indicator.as_deref())
+                .unwrap_or(&self.command_indicator),
+            reply_mode: overrides
+                .and_then(|o| o.reply_mode)
+                .unwrap_or(self.reply_mode),
+            rate_limit: overrides.and_then(|o| o.rate_limit).or(self.rate_limit),
+            global: self,
+            overrides,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseMapping {
+    Ascii,
+    Rfc1459,
+    StrictRfc1459,
+}
+
+// Written out because `#[default]` on enum variants needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for CaseMapping {
+    fn default() -> Self {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl CaseMapping {
+    pub fn fold(self, s: &str) -> String {
+        s.chars()
+            .map(|c| match (self, c) {
+                (_, 'A'..='Z') => c.to_ascii_lowercase(),
+                (CaseMapping::Rfc1459, '^') => '~',
+                (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+                (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+                (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+                _ => c,
+            })
+            .collect()
+    }
+
+    pub fn eq(self, a: &str, b: &str) -> bool {
+        self.fold(a) == self.fold(b)
+    }
 }
 
 impl Config {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        // Load entries via serde
-        let conf = fs::read_to_string(path.as_ref())?;
-        let conf: Config = toml::de::from_str(&conf)?;
+        let conf: Config = ConfigSource::file(path)?
+            .secrets("NESTOR_SECRETS_FILE")?
+            .env("NESTOR_NICK_PASSWORD", "nick_password")
+            .env("NESTOR_SERVER_PASSWORD", "password")
+            .deserialize()?;
+        conf.validate()?;
         Ok(conf)
     }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.bot_settings.command_indicator.is_empty() {
+            return Err(anyhow!("command_indicator must contain at least one entry"));
+        }
+
+        if self.bot_settings.alias_depth == 0 {
+            return Err(anyhow!("alias_depth must be at least 1"));
+        }
+
+        if self.bot_settings.outbound.burst == 0 {
+            return Err(anyhow!("outbound burst must be at least 1"));
+        }
+
+        if let Some(Ircv3Settings {
+            sasl: Some(Sasl::Plain { password, .. }),
+            ..
+        }) = &self.bot_settings.ircv3
+        {
+            if password.is_empty() {
+                return Err(anyhow!("sasl password must not be empty"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Layers configuration sources in order of increasing precedence: the config
+/// file, then an optional secrets file, then individual environment variables.
+pub struct ConfigSource {
+    value: toml::Value,
+    directory: PathBuf,
+}
+
+impl ConfigSource {
+    pub fn file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let conf = fs::read_to_string(path)
+            .with_context(|| format!("unable to read config file {}", path.display()))?;
+        let value = toml::de::from_str(&conf)
+            .with_context(|| format!("unable to parse config file {}", path.display()))?;
+
+        Ok(ConfigSource {
+            value,
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        })
+    }
+
+    pub fn secrets(mut self, env_var: &str) -> Result<Self> {
+        let configured = match &mut self.value {
+            toml::Value::Table(table) => table.remove("secrets_file"),
+            _ => None,
+        };
+
+        let path = match (env::var_os(env_var), configured) {
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(toml::Value::String(path))) => self.directory.join(path),
+            (None, Some(_)) => return Err(anyhow!("secrets_file must be a path")),
+            (None, None) => return Ok(self),
+        };
+
+        let secrets = fs::read_to_string(&path)
+            .with_context(|| format!("unable to read secrets file {}", path.display()))?;
+        let secrets = toml::de::from_str(&secrets)
+            .with_context(|| format!("unable to parse secrets file {}", path.display()))?;
+        merge(&mut self.value, secrets);
+
+        Ok(self)
+    }
+
+    pub fn env(self, env_var: &str, key: &str) -> Self {
+        self.env_with(env_var, key, |name| env::var(name).ok())
+    }
+
+    fn env_with(
+        mut self,
+        env_var: &str,
+        key: &str,
+        lookup: impl FnOnce(&str) -> Option<String>,
+    ) -> Self {
+        if let Some(value) = lookup(env_var) {
+            let mut target = &mut self.value;
+            for part in key.split('.') {
+                if !target.is_table() {
+                    *target = toml::Value::Table(Default::default());
+                }
+
+                target = match target {
+                    toml::Value::Table(table) => table
+                        .entry(part.to_string())
+                        .or_insert_with(|| toml::Value::Table(Default::default())),
+                    _ => unreachable!(),
+                };
+            }
+
+            *target = toml::Value::String(value);
+        }
+
+        self
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self) -> Result<T> {
+        self.value
+            .try_into()
+            .map_err(|err| anyhow!("invalid configuration: {}", err))
+    }
+}
+
+fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+pub struct Reloadable<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Reloadable {
+            current: Arc::new(RwLock::new(Arc::new(value))),
+        }
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) -> Arc<T> {
+        std::mem::replace(&mut *self.current.write().unwrap(), Arc::new(value))
+    }
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Reloadable {
+            current: self.current.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub reconnect_required: Vec<String>,
+}
+
+/// Connection settings that only take effect when the bot reconnects. The
+/// rest, such as `owners`, are read as they are needed.
+const CONNECTION_KEYS: &[&str] = &[
+    "server",
+    "port",
+    "password",
+    "use_tls",
+    "dangerously_accept_invalid_certs",
+    "cert_path",
+    "client_cert_path",
+    "client_cert_pass",
+    "proxy_type",
+    "proxy_server",
+    "proxy_port",
+    "proxy_username",
+    "proxy_password",
+    "nickname",
+    "nick_password",
+    "alt_nicks",
+    "username",
+    "realname",
+    "umodes",
+    "encoding",
+    "ping_time",
+    "ping_timeout",
+    "should_ghost",
+    "ghost_sequence",
+];
+
+impl ReloadReport {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let table = |config: &Config| match toml::Value::try_from(&config.irc_config) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => Default::default(),
+        };
+        let (old_table, new_table) = (table(old), table(new));
+
+        let mut reconnect_required: Vec<String> = CONNECTION_KEYS
+            .iter()
+            .filter(|key| old_table.get(**key) != new_table.get(**key))
+            .map(|key| key.to_string())
+            .collect();
+        reconnect_required.sort();
+
+        // Capabilities and SASL are only negotiated while connecting.
+        let (old, new) = (&old.bot_settings.ircv3, &new.bot_settings.ircv3);
+        let capabilities =
+            |ircv3: &Option<Ircv3Settings>| ircv3.as_ref().map(|ircv3| ircv3.capabilities.clone());
+        if capabilities(old) != capabilities(new) {
+            reconnect_required.push("ircv3.capabilities".into());
+        }
+        let sasl =
+            |ircv3: &Option<Ircv3Settings>| ircv3.as_ref().and_then(|ircv3| ircv3.sasl.clone());
+        if sasl(old) != sasl(new) {
+            reconnect_required.push("ircv3.sasl".into());
+        }
+
+        ReloadReport { reconnect_required }
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.reconnect_required.is_empty() {
+            write!(f, "configuration reloaded")
+        } else {
+            write!(
+                f,
+                "configuration reloaded, reconnect required for: {}",
+                self.reconnect_required.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, ConfigSource, ReloadReport, Reloadable};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// A file in the temp directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl AsRef<Path> for TempFile {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_temp(name: &str, contents: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("nestor-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+
+    fn config(extra: &str) -> Config {
+        toml::de::from_str(&format!(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~"]
+            alias_depth = 2
+            {}
+        "##,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn reloadable_swaps_value() {
+        let reloadable = Reloadable::new(1u32);
+        let handle = reloadable.clone();
+        let before = reloadable.get();
+
+        handle.set(2);
+
+        assert_eq!(*before, 1);
+        assert_eq!(*reloadable.get(), 2);
+    }
+
+    #[test]
+    fn reload_report_lists_connection_changes() {
+        let old = config(r#"nickname = "bot""#);
+        let new = config(
+            r#"nickname = "bot2"
+            disabled_commands = ["qotd"]"#,
+        );
+
+        let report = ReloadReport::between(&old, &new);
+        assert_eq!(report.reconnect_required, ["nickname"]);
+    }
+
+    #[test]
+    fn reload_report_ignores_settings_read_live() {
+        let old = config(r#"nickname = "bot""#);
+        let new = config(
+            r##"nickname = "bot"
+            owners = ["admin"]
+            channels = ["#rust"]"##,
+        );
+
+        let report = ReloadReport::between(&old, &new);
+        assert!(report.reconnect_required.is_empty());
+    }
+
+    #[test]
+    fn reload_report_ignores_bot_settings() {
+        let old = config(r#"nickname = "bot""#);
+        let new = config(
+            r#"nickname = "bot"
+            case_insensitive_commands = true"#,
+        );
+
+        let report = ReloadReport::between(&old, &new);
+        assert!(report.reconnect_required.is_empty());
+    }
+
+    #[test]
+    fn reload_report_lists_ircv3_negotiation_changes() {
+        let old = config(r#"nickname = "bot""#);
+        let new = config(
+            r#"nickname = "bot"
+
+            [ircv3]
+            capabilities = ["message-tags"]
+            sasl = { mechanism = "external" }"#,
+        );
+
+        let report = ReloadReport::between(&old, &new);
+        assert_eq!(
+            report.reconnect_required,
+            ["ircv3.capabilities", "ircv3.sasl"]
+        );
+
+        let threaded = config(
+            r#"nickname = "bot"
+
+            [ircv3]
+            capabilities = ["message-tags"]
+            sasl = { mechanism = "external" }
+            threaded_replies = true"#,
+        );
+        assert!(ReloadReport::between(&new, &threaded)
+            .reconnect_required
+            .is_empty());
+    }
+
+    #[test]
+    fn validation_rejects_empty_indicator() {
+        let config: Config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = []
+            alias_depth = 2
+        "##,
+        )
+        .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn ircv3_defaults() {
+        use super::Sasl;
+
+        let config = config(
+            r#"
+            [ircv3]
+            sasl = { mechanism = "plain", password = "hunter2" }
+            "#,
+        );
+        let ircv3 = config.bot_settings.ircv3.as_ref().unwrap();
+
+        assert!(ircv3.capabilities.iter().any(|cap| cap == "server-time"));
+        assert_eq!(
+            ircv3.sasl,
+            Some(Sasl::Plain {
+                username: None,
+                password: "hunter2".into()
+            })
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn secrets_file_overrides_config() {
+        let secrets = write_temp("secrets.toml", r#"nick_password = "hunter2""#);
+        let config = write_temp(
+            "secrets-config.toml",
+            &format!(
+                r##"
+                secrets_file = "{}"
+                nick_password = "placeholder"
+                blacklisted_users = []
+                command_indicator = ["~"]
+                alias_depth = 2
+            "##,
+                secrets.0.file_name().unwrap().to_str().unwrap()
+            ),
+        );
+
+        let config: Config = ConfigSource::file(&config)
+            .unwrap()
+            .secrets("NESTOR_TEST_UNSET_SECRETS_FILE")
+            .unwrap()
+            .deserialize()
+            .unwrap();
+
+        assert_eq!(config.irc_config.nick_password.as_deref(), Some("hunter2"));
+    }
+
+    #[test]
+    fn env_overrides_nested_keys() {
+        #[derive(serde::Deserialize)]
+        struct Outer {
+            inner: Inner,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Inner {
+            token: String,
+            user: String,
+        }
+
+        let config = write_temp(
+            "env-config.toml",
+            r##"
+            [inner]
+            user = "bot"
+            token = "from-file"
+        "##,
+        );
+        let lookup = |name: &str| match name {
+            "INNER_TOKEN" => Some("from-env".to_string()),
+            _ => None,
+        };
+
+        let outer: Outer = ConfigSource::file(&config)
+            .unwrap()
+            .env_with("INNER_TOKEN", "inner.token", lookup)
+            .env_with("INNER_USER", "inner.user", lookup)
+            .deserialize()
+            .unwrap();
+
+        assert_eq!(outer.inner.token, "from-env");
+        assert_eq!(outer.inner.user, "bot");
+    }
+
+    #[test]
+    fn missing_key_is_named() {
+        let config = write_temp(
+            "missing-config.toml",
+            r##"
+            blacklisted_users = []
+            alias_depth = 2
+        "##,
+        );
+
+        let err = ConfigSource::file(&config)
+            .unwrap()
+            .deserialize::<Config>()
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("command_indicator"));
+    }
 }
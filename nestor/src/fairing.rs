@@ -0,0 +1,124 @@
+use crate::request::Request;
+use crate::response::Response;
+
+pub trait Fairing: Send + Sync {
+    fn on_launch(&self) {}
+
+    fn on_request(&self, _request: &mut Request<'_>) -> Option<Response> {
+        None
+    }
+
+    fn on_response(&self, _request: &Request<'_>, _response: &mut Response) {}
+
+    fn on_shutdown(&self) {}
+}
+
+#[derive(Default)]
+pub(crate) struct Fairings(Vec<Box<dyn Fairing>>);
+
+impl Fairings {
+    pub(crate) fn attach(&mut self, fairing: Box<dyn Fairing>) {
+        self.0.push(fairing);
+    }
+
+    pub(crate) fn on_launch(&self) {
+        for fairing in &self.0 {
+            fairing.on_launch();
+        }
+    }
+
+    pub(crate) fn on_request(&self, request: &mut Request<'_>) -> Option<Response> {
+        self.0
+            .iter()
+            .find_map(|fairing| fairing.on_request(request))
+    }
+
+    pub(crate) fn on_response(&self, request: &Request<'_>, response: &mut Response) {
+        for fairing in &self.0 {
+            fairing.on_response(request, response);
+        }
+    }
+
+    pub(crate) fn on_shutdown(&self) {
+        for fairing in &self.0 {
+            fairing.on_shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Fairing, Fairings};
+    use crate::handler::{Command, CommandRouter};
+    use crate::request::Request;
+    use crate::response::Response;
+    use state::Container;
+    use std::time::Instant;
+
+    struct RejectSpam;
+
+    impl Fairing for RejectSpam {
+        fn on_request(&self, request: &mut Request<'_>) -> Option<Response> {
+            if request.command().arguments.iter().any(|arg| arg == "spam") {
+                Some(Response::None)
+            } else {
+                None
+            }
+        }
+    }
+
+    struct Lowercase;
+
+    impl Fairing for Lowercase {
+        fn on_request(&self, request: &mut Request<'_>) -> Option<Response> {
+            let command = request.command_mut();
+            command.command_str = command.command_str.to_lowercase();
+            None
+        }
+
+        fn on_response(&self, request: &Request<'_>, response: &mut Response) {
+            if let Response::Say(message) = response {
+                *message = message.replace(request.command().source_nick, "someone");
+            }
+        }
+    }
+
+    #[test]
+    fn fairings_filter_and_rewrite() {
+        let config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~"]
+            alias_depth = 2
+        "##,
+        )
+        .unwrap();
+        let container = Container::new();
+        let router = CommandRouter::new();
+        let mut request = Request {
+            config: &config,
+            command: Command::from_command_str("user", "FOO bar").unwrap(),
+            state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+
+        let mut fairings = Fairings::default();
+        fairings.attach(Box::new(RejectSpam));
+        fairings.attach(Box::new(Lowercase));
+
+        assert_eq!(fairings.on_request(&mut request), None);
+        assert_eq!(request.command().command_str, "foo");
+
+        let mut response = Response::Say("hello user".into());
+        fairings.on_response(&request, &mut response);
+        assert_eq!(response, Response::Say("hello someone".into()));
+
+        request.command_mut().arguments = vec!["spam".into()];
+        assert_eq!(fairings.on_request(&mut request), Some(Response::None));
+    }
+}
@@ -1,37 +1,81 @@
-use std::collections::HashMap;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::config::Config;
-use crate::request::Request;
+use crate::config::{CaseMapping, Config, NestorSettings, Reloadable};
+use crate::ignore::IgnoreList;
+use crate::metrics::Metrics;
+use crate::request::{FromRequest, Request};
 use crate::response::{Outcome, Response};
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+use futures::FutureExt;
 
 pub(crate) struct CommandRouter {
-    commands: HashMap<&'static str, &'static dyn CommandHandler>,
-    default: Option<&'static dyn CommandHandler>,
+    table: Reloadable<RouteTable>,
+}
+
+struct RouteTable {
+    commands: HashMap<&'static str, Arc<dyn CommandHandler>>,
+    defaults: Vec<Arc<dyn CommandHandler>>,
+    max_route_words: usize,
 }
 
 impl CommandRouter {
     pub fn new() -> Self {
         CommandRouter {
-            commands: HashMap::new(),
-            default: None,
+            table: Reloadable::new(RouteTable {
+                commands: HashMap::new(),
+                defaults: Vec::new(),
+                max_route_words: 1,
+            }),
         }
     }
 
-    pub fn add_handlers(
-        &mut self,
-        handlers: Vec<(Option<&'static str>, &'static dyn CommandHandler)>,
-    ) {
-        for (label, handler) in handlers {
-            if let Some(label) = label {
-                self.commands.insert(label, handler);
-            } else if self.default.is_none() {
-                self.default = Some(handler);
+    /// Replaces every registered handler. Requests already being routed keep
+    /// using the handlers they started with.
+    pub fn set_handlers(&self, handlers: Vec<Arc<dyn CommandHandler>>) -> Result<(), Error> {
+        let mut table = RouteTable {
+            commands: HashMap::new(),
+            defaults: Vec::new(),
+            max_route_words: 1,
+        };
+
+        for handler in handlers {
+            let label = match handler.route_id() {
+                Some(label) => label,
+                None => {
+                    if table.defaults.iter().any(|d| d.rank() == handler.rank()) {
+                        return Err(anyhow!(
+                            "more than one default handler registered with rank {}",
+                            handler.rank()
+                        ));
+                    }
+
+                    table.defaults.push(handler);
+                    continue;
+                }
+            };
+
+            for route in std::iter::once(&label).chain(handler.aliases()) {
+                if table.commands.insert(route, handler.clone()).is_some() {
+                    return Err(anyhow!("route '{}' is registered more than once", route));
+                }
+
+                table.max_route_words = table.max_route_words.max(route.split(' ').count());
             }
         }
+
+        table.defaults.sort_by_key(|handler| handler.rank());
+        self.table.set(table);
+        Ok(())
     }
 
     pub async fn route<'r>(&'r self, request: &'r Request<'r>) -> Outcome {
@@ -44,26 +88,389 @@ impl CommandRouter {
             return Outcome::Success(Response::None);
         }
 
-        let c: &str = request.command.command_str.as_ref();
-        if let Some(handler) = self.commands.get(c) {
-            match handler.handle(&request) {
-                Ok(fut) => fut.await,
-                Err(err) => return Outcome::Failure(err),
+        // Owners can't be ignored, so a mask that is too broad never locks
+        // them out of removing it.
+        let casemapping = request.config.bot_settings.casemapping;
+        let owner = request
+            .config
+            .irc_config
+            .owners
+            .iter()
+            .any(|owner| casemapping.eq(owner, request.command.source_nick));
+
+        if let Some(ignores) = request.state.try_get::<IgnoreList>().filter(|_| !owner) {
+            let hostmask = match &request.hostmask {
+                Some(hostmask) => hostmask.clone(),
+                None => format!("{}!@", request.command.source_nick),
+            };
+
+            if ignores.is_ignored(&hostmask, casemapping, SystemTime::now()) {
+                return Outcome::Success(Response::None);
+            }
+        }
+
+        let table = self.table.get();
+        if let Some((handler, request)) = table.find_route(request) {
+            // A command disabled under any of its names can't be reached
+            // through another one.
+            let settings = request.config.bot_settings.for_channel(request.channel);
+            let mut names = handler
+                .route_id()
+                .into_iter()
+                .chain(handler.aliases().iter().copied());
+            if names.any(|name| !settings.is_enabled(name)) || !handler.is_enabled(&request) {
+                return Outcome::Success(Response::None);
             }
-        } else if let Some(handler) = &self.default {
-            match handler.handle(&request) {
-                Ok(fut) => fut.await,
-                Err(err) => return Outcome::Failure(err),
+
+            match dispatch(handler.as_ref(), &request).await {
+                Outcome::Fallthrough => {}
+                outcome => return outcome,
             }
-        } else {
-            Outcome::Success(Response::None)
         }
+
+        for handler in table.defaults.iter().filter(|h| h.is_enabled(request)) {
+            match dispatch(handler.as_ref(), request).await {
+                Outcome::Fallthrough => continue,
+                outcome => return outcome,
+            }
+        }
+
+        Outcome::Success(Response::None)
+    }
+
+    pub fn route_names(&self) -> impl Iterator<Item = &'static str> {
+        let names: Vec<_> = self.table.get().commands.keys().copied().collect();
+        names.into_iter()
+    }
+}
+
+impl RouteTable {
+    fn find_route<'r>(
+        &self,
+        request: &Request<'r>,
+    ) -> Option<(&Arc<dyn CommandHandler>, Request<'r>)> {
+        let words: Vec<&str> = std::iter::once(&request.command.command_str)
+            .chain(&request.command.arguments)
+            .map(String::as_str)
+            .collect();
+
+        // Multi-word routes take priority over shorter ones, e.g. `qotd add` over `qotd`.
+        (1..=self.max_route_words.min(words.len()))
+            .rev()
+            .find_map(|len| {
+                let route = words[..len].join(" ");
+                let (route, handler) = self.lookup(&route, &request.config.bot_settings)?;
+                let command = Command {
+                    source_nick: request.command.source_nick,
+                    command_str: route.into(),
+                    arguments: words[len..].iter().map(|&s| s.into()).collect(),
+                };
+
+                Some((
+                    handler,
+                    Request {
+                        config: request.config,
+                        command,
+                        state: request.state,
+                        router: request.router,
+                        network: request.network,
+                        channel: request.channel,
+                        tags: request.tags,
+                        hostmask: request.hostmask.clone(),
+                        received_at: request.received_at,
+                    },
+                ))
+            })
+    }
+
+    fn lookup(
+        &self,
+        route: &str,
+        settings: &NestorSettings,
+    ) -> Option<(&'static str, &Arc<dyn CommandHandler>)> {
+        if let Some((&route, handler)) = self.commands.get_key_value(route) {
+            return Some((route, handler));
+        }
+
+        if !settings.case_insensitive_commands {
+            return None;
+        }
+
+        let folded = settings.casemapping.fold(route);
+        self.commands
+            .iter()
+            .find(|(name, _)| settings.casemapping.fold(name) == folded)
+            .map(|(&route, handler)| (route, handler))
+    }
+}
+
+async fn dispatch<'r>(handler: &'r dyn CommandHandler, request: &'r Request<'r>) -> Outcome {
+    let started = Instant::now();
+    let outcome = execute(handler, request).await;
+
+    if let Some(metrics) = request.state.try_get::<Metrics>() {
+        let route = handler.route_id().unwrap_or("default");
+        let result = match &outcome {
+            Outcome::Success(_) => "success",
+            Outcome::Failure(_) => "failure",
+            Outcome::Forward(_) => "forward",
+            Outcome::Fallthrough => "fallthrough",
+        };
+        metrics.increment(
+            "nestor_commands_total",
+            &[("route", route), ("outcome", result)],
+        );
+        metrics.observe(
+            "nestor_command_duration_seconds",
+            &[("route", route)],
+            started.elapsed().as_secs_f64(),
+        );
+    }
+
+    outcome
+}
+
+async fn execute<'r>(handler: &'r dyn CommandHandler, request: &'r Request<'r>) -> Outcome {
+    let fut = match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request))) {
+        Ok(Ok(fut)) => fut,
+        Ok(Err(err)) => return Outcome::Failure(err),
+        Err(payload) => return panicked(request, payload),
+    };
+    let fut = AssertUnwindSafe(fut).catch_unwind();
+
+    let result = match timeout_for(handler, &request.config.bot_settings) {
+        Some(limit) => match tokio::time::timeout(limit, fut).await {
+            Ok(result) => result,
+            Err(_) => {
+                if let Some(metrics) = request.state.try_get::<Metrics>() {
+                    let route = handler.route_id().unwrap_or("default");
+                    metrics.increment("nestor_command_timeouts_total", &[("route", route)]);
+                }
+                return Outcome::Success(Response::Say("Command timed out".into()));
+            }
+        },
+        None => fut.await,
+    };
+
+    result.unwrap_or_else(|payload| panicked(request, payload))
+}
+
+fn panicked(request: &Request, payload: Box<dyn Any + Send>) -> Outcome {
+    if let Some(metrics) = request.state.try_get::<Metrics>() {
+        metrics.record_panic();
+    }
+
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".into());
+
+    Outcome::Failure(
+        Panic {
+            command: request.command.command_str.clone(),
+            message,
+            backtrace: BACKTRACE.with(|backtrace| backtrace.borrow_mut().take()),
+        }
+        .into(),
+    )
+}
+
+thread_local! {
+    static BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn capture_panic_backtraces() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::force_capture().to_string();
+            BACKTRACE.with(|slot| *slot.borrow_mut() = Some(backtrace));
+            previous(info);
+        }));
+    });
+}
+
+#[derive(Debug)]
+pub struct Panic {
+    pub command: String,
+    pub message: String,
+    pub backtrace: Option<String>,
+}
+
+impl fmt::Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "command '{}' panicked: {}", self.command, self.message)
+    }
+}
+
+impl std::error::Error for Panic {}
+
+fn timeout_for(handler: &dyn CommandHandler, settings: &NestorSettings) -> Option<Duration> {
+    let configured = handler.route_id().and_then(|route| {
+        settings
+            .command_timeouts
+            .iter()
+            .find(|(name, _)| settings.casemapping.eq(name, route))
+            .map(|(_, &seconds)| seconds)
+    });
+
+    configured
+        .map(Duration::from_secs)
+        .or_else(|| handler.timeout())
+        .or_else(|| settings.command_timeout.map(Duration::from_secs))
+}
+
+pub struct Routes<'r> {
+    router: &'r CommandRouter,
+    casemapping: CaseMapping,
+}
+
+impl<'r> Routes<'r> {
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + 'r {
+        self.router.route_names()
+    }
+
+    pub fn suggest(&self, name: &str) -> Vec<&'static str> {
+        suggest(self.casemapping, name, self.names())
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Routes<'a> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(Routes {
+            router: request.router,
+            casemapping: request.config.bot_settings.casemapping,
+        })
+    }
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+pub fn suggest<'c>(
+    casemapping: CaseMapping,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'c str>,
+) -> Vec<&'c str> {
+    let name = casemapping.fold(name);
+    let max_distance = max_suggestion_distance(&name);
+
+    let mut matches: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let distance = strsim::levenshtein(&name, &casemapping.fold(candidate));
+            (distance, candidate)
+        })
+        .filter(|&(distance, _)| distance <= max_distance)
+        .collect();
+
+    matches.sort();
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
+/// The largest edit distance at which `suggest` still offers a candidate for
+/// `name`.
+pub fn max_suggestion_distance(name: &str) -> usize {
+    std::cmp::max(1, name.chars().count() / 3)
+}
+
+/// Returns a `'static` copy of `name`, allocating it only the first time it
+/// is seen. Route names of handlers created at runtime go through here.
+pub(crate) fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+    let mut names = NAMES.lock().unwrap();
+    let names = names.get_or_insert_with(HashSet::new);
+    match names.get(name) {
+        Some(&interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// Like `intern`, for the alias lists of handlers created at runtime.
+pub(crate) fn intern_all(names: &[&'static str]) -> &'static [&'static str] {
+    static LISTS: Mutex<Option<HashSet<&'static [&'static str]>>> = Mutex::new(None);
+
+    let mut lists = LISTS.lock().unwrap();
+    let lists = lists.get_or_insert_with(HashSet::new);
+    match lists.get(names) {
+        Some(&interned) => interned,
+        None => {
+            let interned: &'static [&'static str] = Box::leak(names.to_vec().into_boxed_slice());
+            lists.insert(interned);
+            interned
+        }
+    }
+}
+
+pub(crate) fn discovered() -> Vec<Arc<dyn CommandHandler>> {
+    inventory::iter::<Box<dyn CommandHandler>>
+        .into_iter()
+        .map(|handler| Arc::new(Discovered(handler.as_ref())) as Arc<dyn CommandHandler>)
+        .collect()
+}
+
+struct Discovered(&'static dyn CommandHandler);
+
+impl CommandHandler for Discovered {
+    fn route_id(&self) -> Option<&'static str> {
+        self.0.route_id()
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        self.0.aliases()
+    }
+
+    fn rank(&self) -> isize {
+        self.0.rank()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.0.timeout()
+    }
+
+    fn is_enabled(&self, request: &Request<'_>) -> bool {
+        self.0.is_enabled(request)
+    }
+
+    fn handle<'a, 'r>(
+        &'a self,
+        request: &'a Request<'r>,
+    ) -> Result<Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>, Error> {
+        self.0.handle(request)
     }
 }
 
 pub trait CommandHandler: Send + Sync {
     fn route_id(&self) -> Option<&'static str>;
 
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn rank(&self) -> isize {
+        0
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn is_enabled(&self, _request: &Request<'_>) -> bool {
+        true
+    }
+
     fn handle<'a, 'r>(
         &'a self,
         request: &'a Request<'r>,
@@ -83,9 +490,11 @@ impl<'a> Command<'a> {
         source_nick: &'a str,
         message: &'a str,
         config: &Config,
+        channel: Option<&str>,
     ) -> Option<Command<'a>> {
         let command_str = config
             .bot_settings
+            .for_channel(channel)
             .command_indicator
             .iter()
             .chain(std::iter::once(&format!("{}:", our_nick)))
@@ -120,17 +529,30 @@ impl<'a> Command<'a> {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use crate as nestor;
-    use crate::command;
+    use crate::handler::CommandHandler;
     use crate::request::State;
     use crate::Outcome;
     use crate::Response;
+    use crate::{command, routes};
 
     #[command("foo")]
     fn foo() -> &'static str {
         "foo"
     }
 
+    #[command("foo bar")]
+    fn foo_bar() -> &'static str {
+        "foo bar"
+    }
+
+    #[command("aliased", aliases = ["al", "also aliased"])]
+    fn aliased() -> &'static str {
+        "aliased"
+    }
+
     #[command("failed")]
     fn failed(_n: State<u32>) -> &'static str {
         "failure"
@@ -141,29 +563,115 @@ mod test {
         "default"
     }
 
+    #[command(rank = -1)]
+    fn picky_default(command: &super::Command) -> Outcome {
+        if command.command_str == "picky" {
+            Outcome::Success(Response::Notice("picky".into()))
+        } else {
+            Outcome::Fallthrough
+        }
+    }
+
+    #[command("pass")]
+    fn pass() -> Outcome {
+        Outcome::Fallthrough
+    }
+
+    struct Account(String);
+
+    impl<'a, 'r> crate::request::FromRequestAsync<'a, 'r> for Account {
+        type Error = anyhow::Error;
+        fn from_request(
+            request: &'a crate::request::Request<'r>,
+        ) -> futures::future::BoxFuture<'a, Result<Self, Self::Error>> {
+            Box::pin(async move {
+                futures::future::ready(()).await;
+                match request.command().source_nick {
+                    "" => Err(anyhow::anyhow!("not identified")),
+                    nick => Ok(Account(format!("{}-account", nick))),
+                }
+            })
+        }
+    }
+
+    #[command("whoami")]
+    fn whoami(account: Account) -> String {
+        account.0
+    }
+
+    #[command("stuck", timeout = 0)]
+    async fn stuck() -> &'static str {
+        futures::future::pending::<()>().await;
+        "stuck"
+    }
+
+    #[command("slow")]
+    async fn slow() -> &'static str {
+        futures::future::pending::<()>().await;
+        "slow"
+    }
+
+    #[command("explode")]
+    fn explode() -> &'static str {
+        panic!("boom")
+    }
+
+    #[command("explode later")]
+    async fn explode_later() -> &'static str {
+        futures::future::ready(()).await;
+        panic!("later")
+    }
+
+    fn mounted(handlers: Vec<Box<dyn CommandHandler>>) -> Vec<Arc<dyn CommandHandler>> {
+        handlers.into_iter().map(Arc::from).collect()
+    }
+
     fn run(config: &str, command: &str, source: &str) -> Outcome {
+        run_with_state(config, command, source, &state::Container::new())
+    }
+
+    fn run_with_state(
+        config: &str,
+        command: &str,
+        source: &str,
+        container: &state::Container,
+    ) -> Outcome {
+        run_with_routes(config, command, source, container, super::discovered())
+    }
+
+    fn run_with_routes(
+        config: &str,
+        command: &str,
+        source: &str,
+        container: &state::Container,
+        routes: Vec<Arc<dyn CommandHandler>>,
+    ) -> Outcome {
         use super::{Command, Request};
-        use crate::handler::{CommandHandler, CommandRouter};
-        use futures::executor::block_on;
-        use state::Container;
+        use crate::handler::CommandRouter;
 
         let config = toml::de::from_str(config).unwrap();
 
-        let mut router = CommandRouter::new();
-        let routes = inventory::iter::<Box<dyn CommandHandler>>
-            .into_iter()
-            .map(|route| (route.route_id(), route.as_ref()))
-            .collect::<Vec<_>>();
-        router.add_handlers(routes);
-        let container = Container::new();
+        let router = CommandRouter::new();
+        router.set_handlers(routes).unwrap();
         let command = Command::from_command_str(source, command).unwrap();
         let request = Request {
             config: &config,
             command: command,
-            state: &container,
+            state: container,
+            router: &router,
+            network: "default",
+            channel: Some("#channel"),
+            tags: &[],
+            hostmask: Some(format!("{}!user@example.com", source)),
+            received_at: std::time::Instant::now(),
         };
 
-        block_on(router.route(&request))
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(router.route(&request))
     }
 
     #[test]
@@ -202,6 +710,49 @@ mod test {
         }
     }
 
+    #[test]
+    fn ignore_hostmask() {
+        use crate::ignore::{IgnoreList, IgnoreRule, MemoryStore};
+
+        let config = r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##;
+        let container = state::Container::new();
+        let ignores = IgnoreList::new(MemoryStore).unwrap();
+        ignores
+            .add(IgnoreRule::new("*!*@example.com", "admin"))
+            .unwrap();
+        container.set(ignores);
+
+        match run_with_state(config, "foo", "ignored_user", &container) {
+            Outcome::Success(res) => assert_eq!(res, Response::None),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn owners_are_never_ignored() {
+        use crate::ignore::{IgnoreList, IgnoreRule, MemoryStore};
+
+        let config = r##"
+			owners = ["owner"]
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##;
+        let container = state::Container::new();
+        let ignores = IgnoreList::new(MemoryStore).unwrap();
+        ignores.add(IgnoreRule::new("*!*@*", "admin")).unwrap();
+        container.set(ignores);
+
+        match run_with_state(config, "foo", "Owner", &container) {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
     #[test]
     fn allow_other_users() {
         let result = run(
@@ -256,6 +807,302 @@ mod test {
         }
     }
 
+    #[test]
+    fn ranked_default_handler() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "picky",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("picky".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn route_falls_through_to_default() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "pass",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("default".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn duplicate_default_ranks_rejected() {
+        use crate::handler::CommandRouter;
+
+        let router = CommandRouter::new();
+        let result = router.set_handlers(mounted(routes![default, picky_default, default]));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn multi_word_route() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "foo bar baz",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo bar".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn only_mounted_routes_dispatch() {
+        let result = run_with_routes(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "foo bar baz",
+            "",
+            &state::Container::new(),
+            mounted(routes![foo, default]),
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo".into())),
+            _ => panic!("unexpected outcome"),
+        }
+
+        let result = run_with_routes(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "aliased",
+            "",
+            &state::Container::new(),
+            mounted(routes![foo, default]),
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("default".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn single_word_route_with_args() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "foo baz bar",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn alias_routes() {
+        for alias in &["al", "also aliased"] {
+            let result = run(
+                r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+                alias,
+                "",
+            );
+
+            match result {
+                Outcome::Success(res) => assert_eq!(res, Response::Notice("aliased".into())),
+                _ => panic!("unexpected outcome"),
+            }
+        }
+    }
+
+    #[test]
+    fn disabled_command_ignored() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+			disabled_commands = ["foo"]
+		"##,
+            "foo",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::None),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn disabled_command_ignored_through_alias() {
+        for alias in &["al", "also aliased"] {
+            let result = run(
+                r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+			disabled_commands = ["aliased"]
+		"##,
+                alias,
+                "",
+            );
+
+            match result {
+                Outcome::Success(res) => assert_eq!(res, Response::None),
+                _ => panic!("unexpected outcome"),
+            }
+        }
+    }
+
+    #[test]
+    fn channel_disabled_command_ignored() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+
+			[channel_settings."#Channel"]
+			disabled_commands = ["foo"]
+		"##,
+            "foo",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::None),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn channel_enables_globally_disabled_command() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+			disabled_commands = ["foo"]
+
+			[channel_settings."#channel"]
+			enabled_commands = ["foo"]
+		"##,
+            "foo",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn case_sensitive_by_default() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "FOO",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("default".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn case_insensitive_routes() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+			case_insensitive_commands = true
+		"##,
+            "FOO Bar",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("foo bar".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn casemapping_folds_special_characters() {
+        use crate::config::CaseMapping;
+
+        assert!(CaseMapping::Rfc1459.eq("[nick]^", "{NICK}~"));
+        assert!(CaseMapping::StrictRfc1459.eq("[nick]", "{NICK}"));
+        assert!(!CaseMapping::StrictRfc1459.eq("nick^", "nick~"));
+        assert!(!CaseMapping::Ascii.eq("[nick]", "{nick}"));
+    }
+
+    #[test]
+    fn suggest_nearest_routes() {
+        use super::suggest;
+        use crate::config::CaseMapping;
+
+        let candidates = ["crate", "rfc", "forget", "error", "create"];
+        assert_eq!(
+            suggest(CaseMapping::Rfc1459, "crates", candidates.iter().copied()),
+            ["crate", "create"]
+        );
+        assert_eq!(
+            suggest(CaseMapping::Rfc1459, "FORGET", candidates.iter().copied()),
+            ["forget"]
+        );
+        assert!(suggest(CaseMapping::Rfc1459, "xyz", candidates.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn duplicate_routes_rejected() {
+        use crate::handler::CommandRouter;
+
+        let router = CommandRouter::new();
+        let result = router.set_handlers(mounted(routes![foo, aliased, foo]));
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn parse_empty_command() {
         use super::Command;
@@ -267,7 +1114,7 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "~", &config).unwrap();
+        let command = Command::try_parse("bot", "user", "~", &config, None).unwrap();
 
         assert_eq!(command.command_str, "");
         assert!(command.arguments.is_empty());
@@ -284,7 +1131,7 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "~foo", &config).unwrap();
+        let command = Command::try_parse("bot", "user", "~foo", &config, None).unwrap();
 
         assert_eq!(command.command_str, "foo");
         assert!(command.arguments.is_empty());
@@ -301,7 +1148,7 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "~foo bar baz", &config).unwrap();
+        let command = Command::try_parse("bot", "user", "~foo bar baz", &config, None).unwrap();
 
         assert_eq!(command.command_str, "foo");
         assert_eq!(command.arguments, ["bar", "baz"]);
@@ -318,7 +1165,7 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "foo ~bar baz", &config);
+        let command = Command::try_parse("bot", "user", "foo ~bar baz", &config, None);
 
         assert!(command.is_none());
     }
@@ -334,7 +1181,8 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "test {~foo bar} baz", &config).unwrap();
+        let command =
+            Command::try_parse("bot", "user", "test {~foo bar} baz", &config, None).unwrap();
 
         assert_eq!(command.command_str, "foo");
         assert_eq!(command.arguments, ["bar"]);
@@ -351,12 +1199,35 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "~   foo bar baz  ", &config).unwrap();
+        let command =
+            Command::try_parse("bot", "user", "~   foo bar baz  ", &config, None).unwrap();
 
         assert_eq!(command.command_str, "foo");
         assert_eq!(command.arguments, ["bar", "baz"]);
     }
 
+    #[test]
+    fn parse_command_uses_channel_indicator() {
+        use super::Command;
+        let config = toml::de::from_str(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["~"]
+			alias_depth = 3
+
+			[channel_settings."#rust"]
+			command_indicator = ["?"]
+		"##,
+        )
+        .unwrap();
+
+        let command =
+            Command::try_parse("bot", "user", "?foo bar", &config, Some("#rust")).unwrap();
+        assert_eq!(command.command_str, "foo");
+        assert!(Command::try_parse("bot", "user", "~foo bar", &config, Some("#rust")).is_none());
+        assert!(Command::try_parse("bot", "user", "?foo bar", &config, Some("#other")).is_none());
+    }
+
     #[test]
     fn parse_command_uses_bot_name() {
         use super::Command;
@@ -368,9 +1239,92 @@ mod test {
 		"##,
         )
         .unwrap();
-        let command = Command::try_parse("bot", "user", "bot: foo bar baz", &config).unwrap();
+        let command = Command::try_parse("bot", "user", "bot: foo bar baz", &config, None).unwrap();
 
         assert_eq!(command.command_str, "foo");
         assert_eq!(command.arguments, ["bar", "baz"]);
     }
+
+    #[test]
+    fn attribute_timeout_cancels_command() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##,
+            "stuck",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Say("Command timed out".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn configured_timeout_cancels_command() {
+        let result = run(
+            r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+			command_timeout = 30
+			command_timeouts = { slow = 0 }
+		"##,
+            "slow",
+            "",
+        );
+
+        match result {
+            Outcome::Success(res) => assert_eq!(res, Response::Say("Command timed out".into())),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn panics_become_failures() {
+        use super::Panic;
+        use crate::metrics::Metrics;
+
+        let config = r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##;
+        let container = state::Container::new();
+        container.set(Metrics::default());
+
+        for (command, message) in &[("explode", "boom"), ("explode later", "later")] {
+            match run_with_state(config, command, "", &container) {
+                Outcome::Failure(err) => {
+                    let panic = err.downcast_ref::<Panic>().unwrap();
+                    assert_eq!(panic.message, *message);
+                }
+                _ => panic!("unexpected outcome"),
+            }
+        }
+
+        assert_eq!(container.get::<Metrics>().panics(), 2);
+    }
+
+    #[test]
+    fn async_extractor() {
+        let config = r##"
+			blacklisted_users = []
+			command_indicator = ["!"]
+			alias_depth = 3
+		"##;
+
+        match run(config, "whoami", "user") {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("user-account".into())),
+            _ => panic!("unexpected outcome"),
+        }
+
+        match run(config, "whoami", "") {
+            Outcome::Failure(err) => assert_eq!(err.to_string(), "not identified"),
+            _ => panic!("unexpected outcome"),
+        }
+    }
 }
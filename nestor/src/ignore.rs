@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::CaseMapping;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct IgnoreRule {
+    pub mask: String,
+    pub expires_at: Option<u64>,
+    pub reason: Option<String>,
+    pub added_by: String,
+}
+
+impl IgnoreRule {
+    pub fn new(mask: &str, added_by: &str) -> Self {
+        IgnoreRule {
+            mask: normalize_mask(mask),
+            expires_at: None,
+            reason: None,
+            added_by: added_by.into(),
+        }
+    }
+
+    pub fn matches(&self, hostmask: &str, casemapping: CaseMapping) -> bool {
+        glob_match(&casemapping.fold(&self.mask), &casemapping.fold(hostmask))
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => unix_time(now) >= expires_at,
+            None => false,
+        }
+    }
+}
+
+pub trait IgnoreStore: Send + Sync {
+    fn load(&self) -> Result<Vec<IgnoreRule>>;
+    fn save(&self, rules: &[IgnoreRule]) -> Result<()>;
+}
+
+pub struct MemoryStore;
+
+impl IgnoreStore for MemoryStore {
+    fn load(&self) -> Result<Vec<IgnoreRule>> {
+        Ok(Vec::new())
+    }
+
+    fn save(&self, _rules: &[IgnoreRule]) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct FileStore(pub PathBuf);
+
+#[derive(Serialize, Deserialize, Default)]
+struct IgnoreFile {
+    #[serde(default)]
+    ignore: Vec<IgnoreRule>,
+}
+
+impl IgnoreStore for FileStore {
+    fn load(&self) -> Result<Vec<IgnoreRule>> {
+        if !self.0.exists() {
+            return Ok(Vec::new());
+        }
+
+        let file: IgnoreFile = toml::de::from_str(&fs::read_to_string(&self.0)?)?;
+        Ok(file.ignore)
+    }
+
+    fn save(&self, rules: &[IgnoreRule]) -> Result<()> {
+        let file = IgnoreFile {
+            ignore: rules.to_vec(),
+        };
+        fs::write(&self.0, toml::ser::to_string(&file)?)?;
+        Ok(())
+    }
+}
+
+pub struct IgnoreList {
+    rules: RwLock<Vec<IgnoreRule>>,
+    store: Box<dyn IgnoreStore>,
+}
+
+impl IgnoreList {
+    pub fn new(store: impl IgnoreStore + 'static) -> Result<Self> {
+        Ok(IgnoreList {
+            rules: RwLock::new(store.load()?),
+            store: Box::new(store),
+        })
+    }
+
+    pub fn is_ignored(&self, hostmask: &str, casemapping: CaseMapping, now: SystemTime) -> bool {
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .any(|rule| !rule.is_expired(now) && rule.matches(hostmask, casemapping))
+    }
+
+    pub fn add(&self, rule: IgnoreRule) -> Result<()> {
+        let mut rules = self.rules.write().unwrap();
+        rules.retain(|existing| existing.mask != rule.mask);
+        rules.push(rule);
+        self.persist(&mut rules)
+    }
+
+    pub fn remove(&self, mask: &str) -> Result<bool> {
+        let mask = normalize_mask(mask);
+        let mut rules = self.rules.write().unwrap();
+        let count = rules.len();
+        rules.retain(|rule| rule.mask != mask);
+        let removed = rules.len() != count;
+        self.persist(&mut rules)?;
+        Ok(removed)
+    }
+
+    pub fn list(&self) -> Vec<IgnoreRule> {
+        let now = SystemTime::now();
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rule| !rule.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    fn persist(&self, rules: &mut Vec<IgnoreRule>) -> Result<()> {
+        let now = SystemTime::now();
+        rules.retain(|rule| !rule.is_expired(now));
+        self.store.save(rules)
+    }
+}
+
+pub fn normalize_mask(mask: &str) -> String {
+    match (mask.contains('!'), mask.contains('@')) {
+        (false, false) => format!("{}!*@*", mask),
+        (false, true) => format!("*!{}", mask),
+        (true, false) => format!("{}@*", mask),
+        (true, true) => mask.into(),
+    }
+}
+
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, normalize_mask, IgnoreList, IgnoreRule, MemoryStore};
+    use crate::config::CaseMapping;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("*!*@*.example.com", "troll!~t@host.example.com"));
+        assert!(glob_match("tr?ll!*@*", "troll!user@host"));
+        assert!(!glob_match("troll!*@*", "trolls!user@host"));
+        assert!(!glob_match("*!*@example.com", "nick!user@other.com"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn masks_are_normalized() {
+        assert_eq!(normalize_mask("nick"), "nick!*@*");
+        assert_eq!(normalize_mask("user@host"), "*!user@host");
+        assert_eq!(normalize_mask("nick!user"), "nick!user@*");
+        assert_eq!(normalize_mask("n!u@h"), "n!u@h");
+    }
+
+    #[test]
+    fn ignores_matching_hostmask() {
+        let list = IgnoreList::new(MemoryStore).unwrap();
+        list.add(IgnoreRule::new("Troll", "admin")).unwrap();
+
+        let now = SystemTime::now();
+        assert!(list.is_ignored("troll!user@host", CaseMapping::Rfc1459, now));
+        assert!(!list.is_ignored("friend!user@host", CaseMapping::Rfc1459, now));
+    }
+
+    #[test]
+    fn ignores_expire() {
+        let list = IgnoreList::new(MemoryStore).unwrap();
+        let mut rule = IgnoreRule::new("*!*@spam.host", "admin");
+        rule.expires_at = Some(super::unix_time(SystemTime::now()) + 60);
+        list.add(rule).unwrap();
+
+        let now = SystemTime::now();
+        assert!(list.is_ignored("a!b@spam.host", CaseMapping::Rfc1459, now));
+        assert!(!list.is_ignored(
+            "a!b@spam.host",
+            CaseMapping::Rfc1459,
+            now + Duration::from_secs(120)
+        ));
+    }
+
+    #[test]
+    fn remove_ignore() {
+        let list = IgnoreList::new(MemoryStore).unwrap();
+        list.add(IgnoreRule::new("troll", "admin")).unwrap();
+
+        assert!(list.remove("troll").unwrap());
+        assert!(!list.remove("troll").unwrap());
+        assert!(list.list().is_empty());
+    }
+}
@@ -1,13 +1,26 @@
-use std::sync::Arc;
+use std::any::TypeId;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::Instant;
 
-use crate::config::Config;
-use crate::handler::{Command, CommandHandler, CommandRouter};
+use crate::cap::Negotiation;
+use crate::config::{Config, ReloadReport, Reloadable, ReplyMode};
+use crate::fairing::{Fairing, Fairings};
+use crate::handler::{Command, CommandHandler, CommandRouter, Panic};
+use crate::ignore::{IgnoreList, IgnoreStore, MemoryStore};
+use crate::metrics::Metrics;
+use crate::outbound::{Outbound, Priority, Ticket};
+use crate::plugin::{Plugin, PluginConfig};
+use crate::ratelimit::RateLimiter;
 use crate::request::Request;
 use crate::response::{Outcome, Response};
+use crate::wasm::{KvStore, WasmHost, WasmSettings};
 
 use futures::prelude::*;
 use irc::client::prelude::*;
+use irc::proto::message::Tag;
 use state::Container;
+use tokio::sync::Semaphore;
 
 use anyhow::anyhow;
 pub use anyhow::Error;
@@ -16,37 +29,234 @@ pub use anyhow::Result;
 #[doc(hidden)]
 pub use inventory;
 
-pub use nestor_codegen::command;
+pub use nestor_codegen::{command, routes};
 
 pub mod config;
+pub mod fairing;
 pub mod handler;
+pub mod ignore;
+pub mod metrics;
+pub mod plugin;
 pub mod request;
 pub mod response;
+pub mod wasm;
+
+mod cap;
+mod outbound;
+mod ratelimit;
 
 inventory::collect!(Box<dyn CommandHandler>);
 
+type ConfigLoader =
+    Box<dyn Fn() -> Result<(Vec<(String, Config)>, Box<dyn FnOnce()>)> + Send + Sync>;
+type Catcher = Box<dyn Fn(&Error) -> Response + Send + Sync>;
+
+const DEFAULT_NETWORK: &str = "default";
+const DEFAULT_CONCURRENCY_LIMIT: usize = 64;
+
+struct Network {
+    name: String,
+    config: Reloadable<Config>,
+}
+
 pub struct Nestor {
     state: Container,
-    config: Config,
+    networks: Vec<Arc<Network>>,
+    loader: Option<ConfigLoader>,
     router: CommandRouter,
+    routes: Vec<Arc<dyn CommandHandler>>,
+    discover_routes: bool,
+    wasm: Option<WasmHost>,
+    rate_limiter: RateLimiter,
+    concurrency: Semaphore,
+    catcher: Catcher,
+    fairings: Fairings,
+    metrics_endpoint: Option<SocketAddr>,
 }
 
 impl Nestor {
     pub fn build() -> Self {
         let config = Config::load("nestor.toml").unwrap();
-        Nestor {
-            state: Container::new(),
-            config,
-            router: CommandRouter::new(),
-        }
+        Nestor::with_config(config).reload_with(|| Config::load("nestor.toml"))
     }
 
     pub fn with_config(config: Config) -> Self {
+        Nestor::with_networks(vec![(DEFAULT_NETWORK.to_string(), config)])
+    }
+
+    pub fn with_networks<I, S>(networks: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Config)>,
+        S: Into<String>,
+    {
+        let networks = networks
+            .into_iter()
+            .map(|(name, config)| {
+                Arc::new(Network {
+                    name: name.into(),
+                    config: Reloadable::new(config),
+                })
+            })
+            .collect();
+
         Nestor {
             state: Container::new(),
-            config,
+            networks,
+            loader: None,
             router: CommandRouter::new(),
+            routes: Vec::new(),
+            discover_routes: false,
+            wasm: None,
+            rate_limiter: RateLimiter::new(),
+            concurrency: Semaphore::new(DEFAULT_CONCURRENCY_LIMIT),
+            catcher: Box::new(default_catcher),
+            fairings: Fairings::default(),
+            metrics_endpoint: None,
+        }
+    }
+
+    pub fn reload_with<F>(self, loader: F) -> Self
+    where
+        F: Fn() -> Result<Config> + Send + Sync + 'static,
+    {
+        self.reload_networks_with(move || Ok(vec![(DEFAULT_NETWORK.to_string(), loader()?)]))
+    }
+
+    pub fn reload_networks_with<F>(self, loader: F) -> Self
+    where
+        F: Fn() -> Result<Vec<(String, Config)>> + Send + Sync + 'static,
+    {
+        self.reload_all_with(move || Ok((loader()?, || ())))
+    }
+
+    /// Like `reload_networks_with`, for loaders that read settings of their
+    /// own as well. The function returned along with the network configs
+    /// applies those, and is only called once the reload has succeeded.
+    pub fn reload_all_with<F, A>(mut self, loader: F) -> Self
+    where
+        F: Fn() -> Result<(Vec<(String, Config)>, A)> + Send + Sync + 'static,
+        A: FnOnce() + 'static,
+    {
+        self.loader = Some(Box::new(move || {
+            let (configs, apply) = loader()?;
+            Ok((configs, Box::new(apply) as Box<dyn FnOnce()>))
+        }));
+
+        self
+    }
+
+    pub fn reload(&self) -> Result<ReloadReport> {
+        let loader = self
+            .loader
+            .as_ref()
+            .ok_or(anyhow!("no configuration source to reload from"))?;
+        let (mut configs, apply) = loader()?;
+        for (_, config) in &configs {
+            config.validate()?;
+        }
+
+        if self.wasm.is_some() {
+            self.router.set_handlers(self.handlers()?)?;
         }
+
+        let mut report = ReloadReport::default();
+        for network in &self.networks {
+            let position = configs.iter().position(|(name, _)| *name == network.name);
+            let config = match position {
+                Some(position) => configs.remove(position).1,
+                None => {
+                    report.reconnect_required.push(network.name.clone());
+                    continue;
+                }
+            };
+
+            let old = network.config.set(config);
+            let changes = ReloadReport::between(&old, &network.config.get());
+            if self.networks.len() == 1 {
+                report.reconnect_required.extend(changes.reconnect_required);
+            } else {
+                report.reconnect_required.extend(
+                    changes
+                        .reconnect_required
+                        .into_iter()
+                        .map(|key| format!("{}.{}", network.name, key)),
+                );
+            }
+        }
+        report
+            .reconnect_required
+            .extend(configs.into_iter().map(|(name, _)| name));
+        apply();
+
+        Ok(report)
+    }
+
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency = Semaphore::new(limit);
+
+        self
+    }
+
+    pub fn catch<F>(mut self, catcher: F) -> Self
+    where
+        F: Fn(&Error) -> Response + Send + Sync + 'static,
+    {
+        self.catcher = Box::new(catcher);
+
+        self
+    }
+
+    pub fn metrics_endpoint(mut self, addr: SocketAddr) -> Self {
+        self.metrics_endpoint = Some(addr);
+
+        self
+    }
+
+    pub fn mount(mut self, handlers: Vec<Box<dyn CommandHandler>>) -> Self {
+        self.routes.extend(handlers.into_iter().map(Arc::from));
+
+        self
+    }
+
+    pub fn plugin<P: Plugin>(mut self, plugin: P, config: PluginConfig<P::Config>) -> Self {
+        if !config.enabled {
+            println!("plugin '{}' is disabled", plugin.name());
+            return self;
+        }
+
+        self.routes.extend(plugin::routes(&plugin, &config));
+
+        // Plugins without settings all share `()`, which isn't worth managing.
+        if TypeId::of::<P::Config>() != TypeId::of::<()>() {
+            assert!(
+                self.state.set(config.config),
+                "the config type of plugin '{}' is already managed",
+                plugin.name()
+            );
+        }
+
+        self
+    }
+
+    /// Mount every handler declared with `#[command]` anywhere in the binary.
+    pub fn discover_routes(mut self) -> Self {
+        self.discover_routes = true;
+
+        self
+    }
+
+    /// Load commands from the WebAssembly modules in `settings.directory`,
+    /// rescanning it whenever the configuration is reloaded.
+    pub fn wasm_plugins(mut self, settings: WasmSettings, store: impl KvStore + 'static) -> Self {
+        self.wasm = Some(WasmHost::new(settings, Arc::new(store)));
+
+        self
+    }
+
+    pub fn attach(mut self, fairing: impl Fairing + 'static) -> Self {
+        self.fairings.attach(Box::new(fairing));
+
+        self
     }
 
     pub fn manage<T: Send + Sync + 'static>(self, state: T) -> Self {
@@ -55,71 +265,364 @@ impl Nestor {
         self
     }
 
-    pub fn activate(mut self) {
-        let routes = inventory::iter::<Box<dyn CommandHandler>>
-            .into_iter()
-            .map(|route| (route.route_id(), route.as_ref()))
-            .collect();
-        self.router.add_handlers(routes);
+    pub fn ignore_store(self, store: impl IgnoreStore + 'static) -> Self {
+        let ignores = IgnoreList::new(store).unwrap();
+
+        self.manage(ignores)
+    }
+
+    fn handlers(&self) -> Result<Vec<Arc<dyn CommandHandler>>> {
+        let mut handlers = self.routes.clone();
+        if self.discover_routes {
+            handlers.extend(handler::discovered());
+        }
+        if let Some(wasm) = &self.wasm {
+            handlers.extend(wasm.load()?);
+        }
+
+        Ok(handlers)
+    }
+
+    pub fn activate(self) {
+        if self.state.try_get::<IgnoreList>().is_none() {
+            self.state.set(IgnoreList::new(MemoryStore).unwrap());
+        }
+
+        if self.state.try_get::<Metrics>().is_none() {
+            self.state.set(Metrics::default());
+        }
+
+        handler::capture_panic_backtraces();
+
+        self.router.set_handlers(self.handlers().unwrap()).unwrap();
 
         let nestor = Arc::new(self);
+        nestor.state.set(Reloader(Arc::downgrade(&nestor)));
 
         let mut runtime = tokio::runtime::Runtime::new().unwrap();
         runtime.block_on(async {
-            let mut client = Client::from_config(nestor.config.irc_config.clone())
-                .await
-                .unwrap();
-            client.identify().unwrap();
-            let mut stream = client.stream().unwrap();
-            let client = Arc::new(client);
-            while let Some(message) = stream.next().await.transpose().unwrap() {
-                let nestor = nestor.clone();
-                let client = client.clone();
-                tokio::spawn(async move {
-                    let _ = handle_message(nestor, client, message).await;
-                });
+            #[cfg(unix)]
+            tokio::spawn(reload_on_hangup(nestor.clone()));
+
+            if let Some(addr) = nestor.metrics_endpoint {
+                #[cfg(feature = "metrics")]
+                tokio::spawn(metrics::serve(addr, nestor.clone()));
+                #[cfg(not(feature = "metrics"))]
+                println!("metrics endpoint {} requires the `metrics` feature", addr);
+            }
+
+            nestor.fairings.on_launch();
+
+            let connections = nestor
+                .networks
+                .iter()
+                .map(|network| connect(nestor.clone(), network.clone()));
+            let connections = future::join_all(connections);
+            match future::select(connections, Box::pin(tokio::signal::ctrl_c())).await {
+                future::Either::Left((results, _)) => {
+                    for result in results {
+                        if let Err(err) = result {
+                            println!("{:?}", err);
+                        }
+                    }
+                }
+                future::Either::Right(_) => println!("shutting down"),
+            }
+
+            nestor.fairings.on_shutdown();
+        });
+    }
+}
+
+async fn connect(nestor: Arc<Nestor>, network: Arc<Network>) -> Result<()> {
+    let config = network.config.get();
+    if let Some(metrics) = nestor.state.try_get::<Metrics>() {
+        metrics.increment(
+            "nestor_connection_attempts_total",
+            &[("network", &network.name)],
+        );
+    }
+
+    let mut client = Client::from_config(config.irc_config.clone())
+        .await
+        .map_err(|err| anyhow!("unable to connect to {}: {}", network.name, err))?;
+    let mut negotiation = match &config.bot_settings.ircv3 {
+        Some(ircv3) => Some(Negotiation::start(&client, &config.irc_config, ircv3)?),
+        None => {
+            client.identify()?;
+            None
+        }
+    };
+    let mut stream = client.stream()?;
+    let client = Arc::new(client);
+    let (mut outbound, receiver) = Outbound::new();
+    tokio::spawn(outbound::run(network.clone(), client.clone(), receiver));
+    while let Some(message) = stream.next().await.transpose()? {
+        if let Some(current) = &mut negotiation {
+            current.handle(&client, &message)?;
+            if current.is_done() {
+                negotiation = None;
             }
+        }
+
+        let ticket = outbound.ticket(message.response_target().unwrap_or_default());
+        let nestor = nestor.clone();
+        let network = network.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let _ = handle_message(nestor, network, client, ticket, message).await;
         });
     }
+
+    Ok(())
+}
+
+pub struct Reloader(Weak<Nestor>);
+
+impl Reloader {
+    pub fn reload(&self) -> Result<ReloadReport> {
+        self.0
+            .upgrade()
+            .ok_or(anyhow!("bot is shutting down"))?
+            .reload()
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(nestor: Arc<Nestor>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            println!("unable to listen for SIGHUP: {:?}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match nestor.reload() {
+            Ok(report) => println!("{}", report),
+            Err(err) => println!("failed to reload configuration: {:?}", err),
+        }
+    }
 }
 
 async fn handle_message(
     nestor: Arc<Nestor>,
+    network: Arc<Network>,
     client: Arc<Client>,
+    ticket: Ticket,
     message: Message,
 ) -> Result<(), Error> {
-    if let Some((responder, mut request)) = Request::from_message(&nestor, &client, &message) {
-        for _ in 0..nestor.config.bot_settings.alias_depth {
-            let response = nestor.router.route(&request).await;
-            let response = match response {
+    let config = network.config.get();
+    if let Some((responder, mut request)) =
+        Request::from_message(&nestor, &network.name, &config, &client, &message)
+    {
+        let settings = config.bot_settings.for_channel(request.channel);
+        if let Some(limit) = settings.rate_limit {
+            let key = format!(
+                "{}/{}",
+                network.name,
+                request.channel.unwrap_or(request.command.source_nick)
+            );
+            if !nestor.rate_limiter.check(&key, limit, Instant::now()) {
+                return Ok(());
+            }
+        }
+
+        // Commands beyond the limit are dropped rather than queued, so slow
+        // handlers can't pile up tasks.
+        let _permit = match nestor.concurrency.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                println!(
+                    "dropping command from {}: too many commands running",
+                    request.command.source_nick
+                );
+                return Ok(());
+            }
+        };
+
+        let casemapping = config.bot_settings.casemapping;
+        let priority = if config
+            .irc_config
+            .owners
+            .iter()
+            .any(|owner| casemapping.eq(owner, request.command.source_nick))
+        {
+            Priority::High
+        } else {
+            Priority::Normal
+        };
+
+        let mut response = nestor.fairings.on_request(&mut request);
+        let mut remaining = config.bot_settings.alias_depth;
+        while response.is_none() && remaining > 0 {
+            remaining -= 1;
+            response = match nestor.router.route(&request).await {
                 Outcome::Forward(c) => {
                     request = Request {
-                        config: &nestor.config,
+                        config: &config,
                         state: &nestor.state,
+                        router: &nestor.router,
+                        network: request.network,
+                        channel: request.channel,
+                        tags: request.tags,
+                        hostmask: request.hostmask.clone(),
+                        received_at: request.received_at,
                         command: Command::from_command_str(request.command.source_nick, &c)
                             .ok_or(anyhow!("Internal error with command alias"))?,
                     };
                     continue;
                 }
-                Outcome::Success(response) => response,
-                Outcome::Failure(err) => {
-                    println!("{:?}", err);
-                    Response::Say("Unexpected error executing command".into())
-                }
+                Outcome::Success(response) => Some(response),
+                Outcome::Fallthrough => Some(Response::None),
+                Outcome::Failure(err) => Some((nestor.catcher)(&err)),
             };
+        }
 
-            match response {
-                Response::Say(message) => client.send_privmsg(responder, &message)?,
-                Response::Act(message) => client.send_action(responder, &message)?,
-                Response::Notice(message) => client.send_notice(responder, &message)?,
-                Response::None => {}
-            }
+        let mut response = match response {
+            Some(response) => match (settings.reply_mode, response) {
+                (ReplyMode::Say, Response::Notice(message)) => Response::Say(message),
+                (ReplyMode::Notice, Response::Say(message)) => Response::Notice(message),
+                (_, response) => response,
+            },
+            None => Response::Notice("alias depth too deep".into()),
+        };
+        nestor.fairings.on_response(&request, &mut response);
 
-            return Ok(());
-        }
+        let reply_to = config
+            .bot_settings
+            .ircv3
+            .as_ref()
+            .filter(|ircv3| ircv3.threaded_replies)
+            .and_then(|_| request.tags.iter().find(|Tag(key, _)| key == "msgid"))
+            .and_then(|Tag(_, msgid)| msgid.as_deref());
 
-        client.send_notice(responder, "alias depth too deep")?;
+        if let Some(message) = response_message(responder, response, reply_to) {
+            ticket.send(message, priority);
+        }
     }
 
     Ok(())
 }
+
+fn default_catcher(err: &Error) -> Response {
+    match err.downcast_ref::<Panic>() {
+        Some(Panic {
+            backtrace: Some(backtrace),
+            ..
+        }) => println!("{}\n{}", err, backtrace),
+        _ => println!("{:?}", err),
+    }
+
+    Response::Say("Unexpected error executing command".into())
+}
+
+fn response_message(target: &str, response: Response, reply_to: Option<&str>) -> Option<Message> {
+    let command = match response {
+        Response::Say(message) => irc::proto::Command::PRIVMSG(target.into(), message),
+        Response::Act(message) => {
+            irc::proto::Command::PRIVMSG(target.into(), format!("\u{1}ACTION {}\u{1}", message))
+        }
+        Response::Notice(message) => irc::proto::Command::NOTICE(target.into(), message),
+        Response::None => return None,
+    };
+
+    Some(Message {
+        tags: reply_to.map(|msgid| vec![Tag("+draft/reply".into(), Some(msgid.into()))]),
+        prefix: None,
+        command,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{response_message, Nestor};
+    use crate::response::Response;
+    use irc::client::prelude::*;
+    use irc::proto::message::Tag;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn config(command_indicator: &str) -> crate::config::Config {
+        toml::de::from_str(&format!(
+            r##"
+			blacklisted_users = []
+			command_indicator = {}
+			alias_depth = 2
+		"##,
+            command_indicator
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn failed_reloads_apply_nothing() {
+        let applied = Arc::new(AtomicUsize::new(0));
+        let counter = applied.clone();
+        let valid = Arc::new(AtomicBool::new(false));
+        let toggle = valid.clone();
+        let nestor = Nestor::with_config(config(r#"["~"]"#)).reload_all_with(move || {
+            let indicator = if toggle.load(Ordering::SeqCst) {
+                r#"["!"]"#
+            } else {
+                "[]"
+            };
+            let counter = counter.clone();
+            Ok((vec![("default".into(), config(indicator))], move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }))
+        });
+
+        assert!(nestor.reload().is_err());
+        assert_eq!(applied.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            nestor.networks[0]
+                .config
+                .get()
+                .bot_settings
+                .command_indicator,
+            ["~"]
+        );
+
+        valid.store(true, Ordering::SeqCst);
+        assert!(nestor.reload().is_ok());
+        assert_eq!(applied.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            nestor.networks[0]
+                .config
+                .get()
+                .bot_settings
+                .command_indicator,
+            ["!"]
+        );
+    }
+
+    #[test]
+    fn replies_reference_msgid() {
+        let message = response_message("#rust", Response::Say("hi".into()), Some("abc")).unwrap();
+
+        assert_eq!(
+            message.tags,
+            Some(vec![Tag("+draft/reply".into(), Some("abc".into()))])
+        );
+        assert_eq!(
+            message.command,
+            Command::PRIVMSG("#rust".into(), "hi".into())
+        );
+    }
+
+    #[test]
+    fn replies_without_msgid_are_untagged() {
+        let message = response_message("#rust", Response::Act("waves".into()), None).unwrap();
+
+        assert_eq!(message.tags, None);
+        assert_eq!(
+            message.command,
+            Command::PRIVMSG("#rust".into(), "\u{1}ACTION waves\u{1}".into())
+        );
+        assert!(response_message("#rust", Response::None, Some("abc")).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "metrics")]
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::fmt::Write;
+#[cfg(feature = "metrics")]
+use std::sync::Mutex;
+
+#[cfg(feature = "metrics")]
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    panics: AtomicU64,
+    #[cfg(feature = "metrics")]
+    registry: Mutex<Registry>,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<String, BTreeMap<String, u64>>,
+    histograms: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        #[cfg(feature = "metrics")]
+        {
+            let mut registry = self.registry.lock().unwrap();
+            *registry
+                .counters
+                .entry(name.into())
+                .or_default()
+                .entry(render_labels(labels))
+                .or_default() += 1;
+        }
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        #[cfg(feature = "metrics")]
+        {
+            let mut registry = self.registry.lock().unwrap();
+            let histogram = registry
+                .histograms
+                .entry(name.into())
+                .or_default()
+                .entry(render_labels(labels))
+                .or_default();
+            histogram.buckets.resize(BUCKETS.len(), 0);
+            for (bucket, &bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+                if value <= bound {
+                    *bucket += 1;
+                }
+            }
+            histogram.sum += value;
+            histogram.count += 1;
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE nestor_panics_total counter");
+        let _ = writeln!(out, "nestor_panics_total {}", self.panics());
+
+        for (name, series) in &registry.counters {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(out, "{}{} {}", name, braces(labels), value);
+            }
+        }
+
+        for (name, series) in &registry.histograms {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (labels, histogram) in series {
+                for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                    let le = format!("le=\"{}\"", bound);
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        name,
+                        braces(&join_labels(labels, &le)),
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    braces(&join_labels(labels, "le=\"+Inf\"")),
+                    histogram.count
+                );
+                let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), histogram.sum);
+                let _ = writeln!(out, "{}_count{} {}", name, braces(labels), histogram.count);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(feature = "metrics")]
+fn join_labels(labels: &str, extra: &str) -> String {
+    if labels.is_empty() {
+        extra.into()
+    } else {
+        format!("{},{}", labels, extra)
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+#[cfg(feature = "metrics")]
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[cfg(feature = "metrics")]
+pub(crate) async fn serve(addr: std::net::SocketAddr, nestor: std::sync::Arc<crate::Nestor>) {
+    use tokio::net::TcpListener;
+
+    let mut listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("unable to serve metrics on {}: {:?}", addr, err);
+            return;
+        }
+    };
+
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                println!("failed to accept metrics connection: {:?}", err);
+                continue;
+            }
+        };
+
+        // Each scrape gets its own task, so a client that never sends its
+        // request can't hold up anyone else.
+        tokio::spawn(respond(socket, nestor.clone()));
+    }
+}
+
+#[cfg(feature = "metrics")]
+async fn respond(mut socket: tokio::net::TcpStream, nestor: std::sync::Arc<crate::Nestor>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::timeout;
+
+    let mut request = [0; 1024];
+    if timeout(REQUEST_TIMEOUT, socket.read(&mut request))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let body = nestor.state.get::<Metrics>().render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = timeout(REQUEST_TIMEOUT, socket.write_all(response.as_bytes())).await;
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use super::Metrics;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.increment("nestor_commands_total", &[("route", "foo")]);
+        metrics.increment("nestor_commands_total", &[("route", "foo")]);
+        metrics.observe("nestor_command_duration_seconds", &[], 0.02);
+
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE nestor_commands_total counter\n"));
+        assert!(text.contains("nestor_commands_total{route=\"foo\"} 2\n"));
+        assert!(text.contains("nestor_command_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("nestor_command_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("nestor_command_duration_seconds_count 1\n"));
+    }
+}
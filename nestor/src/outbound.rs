@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::OutboundSettings;
+use crate::Network;
+
+use irc::client::prelude::*;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Priority {
+    High,
+    Normal,
+}
+
+pub(crate) struct Outgoing {
+    message: Message,
+    priority: Priority,
+    queued_at: Instant,
+}
+
+pub(crate) enum Event {
+    Issued {
+        sequence: u64,
+        target: String,
+        issued_at: Instant,
+    },
+    Resolved {
+        sequence: u64,
+        reply: Option<Outgoing>,
+    },
+}
+
+pub(crate) struct Outbound {
+    sender: UnboundedSender<Event>,
+    next: u64,
+}
+
+impl Outbound {
+    pub(crate) fn new() -> (Self, UnboundedReceiver<Event>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Outbound { sender, next: 0 }, receiver)
+    }
+
+    /// Reserves a place for the reply to an incoming message, so replies to
+    /// the same target go out in the order the messages arrived.
+    pub(crate) fn ticket(&mut self, target: &str) -> Ticket {
+        let sequence = self.next;
+        self.next += 1;
+        let _ = self.sender.send(Event::Issued {
+            sequence,
+            target: target.into(),
+            issued_at: Instant::now(),
+        });
+
+        Ticket {
+            sequence,
+            sender: self.sender.clone(),
+            resolved: false,
+        }
+    }
+}
+
+/// A reserved place in the outbound queue. Dropping it without sending
+/// anything lets the replies behind it through.
+pub(crate) struct Ticket {
+    sequence: u64,
+    sender: UnboundedSender<Event>,
+    resolved: bool,
+}
+
+impl Ticket {
+    pub(crate) fn send(mut self, message: Message, priority: Priority) {
+        self.resolve(Some(Outgoing {
+            message,
+            priority,
+            queued_at: Instant::now(),
+        }));
+    }
+
+    fn resolve(&mut self, reply: Option<Outgoing>) {
+        if !self.resolved {
+            self.resolved = true;
+            let _ = self.sender.send(Event::Resolved {
+                sequence: self.sequence,
+                reply,
+            });
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.resolve(None);
+    }
+}
+
+struct Pending {
+    target: String,
+    issued_at: Instant,
+    reply: Option<Option<Outgoing>>,
+}
+
+/// Holds replies back until every earlier message to the same target has
+/// been answered, or has gone unanswered for longer than replies may wait.
+#[derive(Default)]
+struct Sequencer {
+    pending: BTreeMap<u64, Pending>,
+}
+
+impl Sequencer {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Issued {
+                sequence,
+                target,
+                issued_at,
+            } => {
+                self.pending.insert(
+                    sequence,
+                    Pending {
+                        target,
+                        issued_at,
+                        reply: None,
+                    },
+                );
+            }
+            Event::Resolved { sequence, reply } => {
+                if let Some(pending) = self.pending.get_mut(&sequence) {
+                    pending.reply = Some(reply);
+                }
+            }
+        }
+    }
+
+    /// Queues the replies nothing is waiting on any more. Messages that have
+    /// been unanswered for longer than `stale_after` are given up on, and a
+    /// reply that shows up for them later is dropped.
+    fn release(&mut self, queue: &mut Queue, stale_after: Duration, now: Instant) {
+        let mut waiting = HashSet::new();
+        let mut released = Vec::new();
+        for (&sequence, pending) in self.pending.iter_mut() {
+            let Pending {
+                target,
+                issued_at,
+                reply,
+            } = pending;
+            match reply {
+                None if now.duration_since(*issued_at) > stale_after => {
+                    released.push(sequence);
+                }
+                None => {
+                    waiting.insert(target.as_str());
+                }
+                Some(reply) if !waiting.contains(target.as_str()) => {
+                    if let Some(outgoing) = reply.take() {
+                        queue.push(outgoing);
+                    }
+                    released.push(sequence);
+                }
+                Some(_) => {}
+            }
+        }
+
+        for sequence in released {
+            self.pending.remove(&sequence);
+        }
+    }
+
+    /// When the oldest unanswered message goes stale.
+    fn next_expiry(&self, stale_after: Duration) -> Option<Instant> {
+        self.pending
+            .values()
+            .filter(|pending| pending.reply.is_none())
+            .map(|pending| pending.issued_at + stale_after)
+            .min()
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    high: VecDeque<Outgoing>,
+    normal: VecDeque<Outgoing>,
+}
+
+impl Queue {
+    fn push(&mut self, outgoing: Outgoing) {
+        match outgoing.priority {
+            Priority::High => self.high.push_back(outgoing),
+            Priority::Normal => self.normal.push_back(outgoing),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    fn drop_stale(&mut self, stale_after: Duration, now: Instant) {
+        let fresh = |outgoing: &Outgoing| now.duration_since(outgoing.queued_at) <= stale_after;
+        self.high.retain(fresh);
+        self.normal.retain(fresh);
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.high
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+            .map(|outgoing| outgoing.message)
+    }
+}
+
+struct Throttle {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Throttle {
+    fn new(settings: &OutboundSettings, now: Instant) -> Self {
+        Throttle {
+            tokens: f64::from(settings.burst),
+            updated: now,
+        }
+    }
+
+    fn delay(&mut self, settings: &OutboundSettings, now: Instant) -> Duration {
+        let interval = Duration::from_millis(settings.interval_millis).as_secs_f64();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = if interval > 0.0 {
+            (self.tokens + elapsed / interval).min(f64::from(settings.burst))
+        } else {
+            f64::from(settings.burst)
+        };
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) * interval)
+        }
+    }
+}
+
+pub(crate) async fn run(
+    network: Arc<Network>,
+    client: Arc<Client>,
+    mut receiver: UnboundedReceiver<Event>,
+) {
+    let mut sequencer = Sequencer::default();
+    let mut queue = Queue::default();
+    let mut throttle = Throttle::new(&network.config.get().bot_settings.outbound, Instant::now());
+
+    loop {
+        while let Ok(event) = receiver.try_recv() {
+            sequencer.handle(event);
+        }
+
+        // Stale messages are dropped before a token is taken for them.
+        let settings = network.config.get().bot_settings.outbound;
+        let stale_after = Duration::from_secs(settings.stale_after_seconds);
+        sequencer.release(&mut queue, stale_after, Instant::now());
+        queue.drop_stale(stale_after, Instant::now());
+
+        if queue.is_empty() {
+            // Wake up when an unanswered message goes stale, so the replies
+            // behind it aren't held until the next event.
+            let event = match sequencer.next_expiry(stale_after) {
+                Some(expiry) => {
+                    let wait = expiry.saturating_duration_since(Instant::now());
+                    match tokio::time::timeout(wait, receiver.recv()).await {
+                        Ok(event) => event,
+                        Err(_) => continue,
+                    }
+                }
+                None => receiver.recv().await,
+            };
+            match event {
+                Some(event) => sequencer.handle(event),
+                None => return,
+            }
+            continue;
+        }
+
+        let delay = throttle.delay(&settings, Instant::now());
+        if delay > Duration::from_secs(0) {
+            tokio::time::delay_for(delay).await;
+            continue;
+        }
+
+        if let Some(message) = queue.pop() {
+            if let Err(err) = client.send(message) {
+                println!("failed to send message: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Outgoing, Priority, Queue, Sequencer, Throttle};
+    use crate::config::OutboundSettings;
+    use irc::client::prelude::*;
+    use std::time::{Duration, Instant};
+
+    fn outgoing(text: &str, priority: Priority, queued_at: Instant) -> Outgoing {
+        Outgoing {
+            message: Command::PRIVMSG("#rust".into(), text.into()).into(),
+            priority,
+            queued_at,
+        }
+    }
+
+    fn text(message: Option<Message>) -> Option<String> {
+        match message?.command {
+            Command::PRIVMSG(_, text) => Some(text),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn high_priority_goes_first() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.push(outgoing("one", Priority::Normal, now));
+        queue.push(outgoing("two", Priority::Normal, now));
+        queue.push(outgoing("admin", Priority::High, now));
+
+        assert_eq!(text(queue.pop()), Some("admin".into()));
+        assert_eq!(text(queue.pop()), Some("one".into()));
+        assert_eq!(text(queue.pop()), Some("two".into()));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn stale_messages_are_dropped() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.push(outgoing("old", Priority::Normal, now));
+        queue.push(outgoing(
+            "new",
+            Priority::Normal,
+            now + Duration::from_secs(50),
+        ));
+
+        queue.drop_stale(Duration::from_secs(30), now + Duration::from_secs(60));
+        assert_eq!(text(queue.pop()), Some("new".into()));
+        assert!(queue.is_empty());
+    }
+
+    const STALE_AFTER: Duration = Duration::from_secs(30);
+
+    fn issued(sequence: u64, target: &str, issued_at: Instant) -> Event {
+        Event::Issued {
+            sequence,
+            target: target.into(),
+            issued_at,
+        }
+    }
+
+    #[test]
+    fn replies_keep_request_order_per_target() {
+        let now = Instant::now();
+        let mut sequencer = Sequencer::default();
+        let mut queue = Queue::default();
+        for (sequence, target) in [(0, "#rust"), (1, "#rust"), (2, "#other")].iter() {
+            sequencer.handle(issued(*sequence, target, now));
+        }
+        let resolved = |sequence, reply: Option<&str>| Event::Resolved {
+            sequence,
+            reply: reply.map(|text| outgoing(text, Priority::Normal, now)),
+        };
+
+        sequencer.handle(resolved(1, Some("second")));
+        sequencer.release(&mut queue, STALE_AFTER, now);
+        assert!(queue.is_empty());
+
+        sequencer.handle(resolved(2, Some("other")));
+        sequencer.release(&mut queue, STALE_AFTER, now);
+        assert_eq!(text(queue.pop()), Some("other".into()));
+
+        sequencer.handle(resolved(0, Some("first")));
+        sequencer.release(&mut queue, STALE_AFTER, now);
+        assert_eq!(text(queue.pop()), Some("first".into()));
+        assert_eq!(text(queue.pop()), Some("second".into()));
+        assert!(sequencer.pending.is_empty());
+    }
+
+    #[test]
+    fn unanswered_messages_release_later_replies() {
+        let now = Instant::now();
+        let mut sequencer = Sequencer::default();
+        let mut queue = Queue::default();
+        for sequence in 0..2 {
+            sequencer.handle(issued(sequence, "#rust", now));
+        }
+
+        let reply = Event::Resolved {
+            sequence: 1,
+            reply: Some(outgoing("reply", Priority::Normal, now)),
+        };
+        sequencer.handle(reply);
+        sequencer.release(&mut queue, STALE_AFTER, now);
+        assert!(queue.is_empty());
+
+        let ignored = Event::Resolved {
+            sequence: 0,
+            reply: None,
+        };
+        sequencer.handle(ignored);
+        sequencer.release(&mut queue, STALE_AFTER, now);
+        assert_eq!(text(queue.pop()), Some("reply".into()));
+    }
+
+    #[test]
+    fn messages_that_never_resolve_go_stale() {
+        let now = Instant::now();
+        let mut sequencer = Sequencer::default();
+        let mut queue = Queue::default();
+        sequencer.handle(issued(0, "#rust", now));
+        sequencer.handle(issued(1, "#rust", now + Duration::from_secs(10)));
+        assert_eq!(sequencer.next_expiry(STALE_AFTER), Some(now + STALE_AFTER));
+
+        let reply = Event::Resolved {
+            sequence: 1,
+            reply: Some(outgoing("reply", Priority::Normal, now)),
+        };
+        sequencer.handle(reply);
+        sequencer.release(&mut queue, STALE_AFTER, now + STALE_AFTER);
+        assert!(queue.is_empty());
+        assert_eq!(sequencer.next_expiry(STALE_AFTER), Some(now + STALE_AFTER));
+
+        sequencer.release(&mut queue, STALE_AFTER, now + Duration::from_secs(31));
+        assert_eq!(text(queue.pop()), Some("reply".into()));
+        assert!(sequencer.pending.is_empty());
+        assert_eq!(sequencer.next_expiry(STALE_AFTER), None);
+
+        // A reply for a message that was given up on is dropped.
+        let late = Event::Resolved {
+            sequence: 0,
+            reply: Some(outgoing("late", Priority::Normal, now)),
+        };
+        sequencer.handle(late);
+        sequencer.release(&mut queue, STALE_AFTER, now + Duration::from_secs(31));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn throttle_allows_burst_then_waits() {
+        let settings = OutboundSettings {
+            burst: 2,
+            interval_millis: 1000,
+            stale_after_seconds: 30,
+        };
+        let now = Instant::now();
+        let mut throttle = Throttle::new(&settings, now);
+
+        assert_eq!(throttle.delay(&settings, now), Duration::from_secs(0));
+        assert_eq!(throttle.delay(&settings, now), Duration::from_secs(0));
+        assert_eq!(throttle.delay(&settings, now), Duration::from_secs(1));
+        assert_eq!(
+            throttle.delay(&settings, now + Duration::from_secs(1)),
+            Duration::from_secs(0)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::handler::{intern, intern_all, CommandHandler};
+use crate::request::Request;
+use crate::response::Outcome;
+
+use anyhow::Error;
+use serde::Deserialize;
+
+/// A named group of commands sharing a typed config section.
+///
+/// A plugin's `Config` is managed as state once the plugin is mounted, so its
+/// handlers can take it as a `State<'_, Config>` parameter. Each plugin with
+/// settings needs a `Config` type of its own; plugins without any use `()`.
+pub trait Plugin {
+    type Config: Send + Sync + 'static;
+
+    fn name(&self) -> &'static str;
+
+    fn routes(&self) -> Vec<Box<dyn CommandHandler>>;
+}
+
+#[derive(Deserialize)]
+pub struct PluginConfig<C> {
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub networks: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<String>,
+    #[serde(flatten)]
+    pub config: C,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl<C: Default> Default for PluginConfig<C> {
+    fn default() -> Self {
+        PluginConfig {
+            enabled: true,
+            prefix: None,
+            networks: Vec::new(),
+            channels: Vec::new(),
+            config: C::default(),
+        }
+    }
+}
+
+struct Scope {
+    networks: Vec<String>,
+    channels: Vec<String>,
+}
+
+impl Scope {
+    fn contains(&self, request: &Request<'_>) -> bool {
+        let casemapping = request.config.bot_settings.casemapping;
+        let network =
+            self.networks.is_empty() || self.networks.iter().any(|name| name == request.network());
+        let channel = self.channels.is_empty()
+            || request.channel().map_or(false, |channel| {
+                self.channels
+                    .iter()
+                    .any(|name| casemapping.eq(name, channel))
+            });
+
+        network && channel
+    }
+}
+
+struct PluginHandler {
+    handler: Box<dyn CommandHandler>,
+    route: Option<&'static str>,
+    aliases: &'static [&'static str],
+    scope: Arc<Scope>,
+}
+
+impl CommandHandler for PluginHandler {
+    fn route_id(&self) -> Option<&'static str> {
+        self.route
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        self.aliases
+    }
+
+    fn rank(&self) -> isize {
+        self.handler.rank()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.handler.timeout()
+    }
+
+    fn is_enabled(&self, request: &Request<'_>) -> bool {
+        self.scope.contains(request) && self.handler.is_enabled(request)
+    }
+
+    fn handle<'a, 'r>(
+        &'a self,
+        request: &'a Request<'r>,
+    ) -> Result<Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>, Error> {
+        self.handler.handle(request)
+    }
+}
+
+/// Wraps a plugin's routes so they answer under its prefix and only in its
+/// configured networks and channels. Prefixed names and alias lists are
+/// interned to satisfy `CommandHandler::route_id` and `aliases`, so mounting
+/// the same plugin again doesn't allocate them again.
+pub(crate) fn routes<P: Plugin>(
+    plugin: &P,
+    config: &PluginConfig<P::Config>,
+) -> Vec<Arc<dyn CommandHandler>> {
+    let scope = Arc::new(Scope {
+        networks: config.networks.clone(),
+        channels: config.channels.clone(),
+    });
+    let prefixed = |route: &'static str| -> &'static str {
+        match &config.prefix {
+            Some(prefix) => intern(&format!("{} {}", prefix, route)),
+            None => route,
+        }
+    };
+
+    plugin
+        .routes()
+        .into_iter()
+        .map(|handler| {
+            let route = handler.route_id().map(prefixed);
+            let aliases: Vec<_> = handler.aliases().iter().copied().map(prefixed).collect();
+
+            Arc::new(PluginHandler {
+                handler,
+                route,
+                aliases: intern_all(&aliases),
+                scope: scope.clone(),
+            }) as Arc<dyn CommandHandler>
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Plugin, PluginConfig};
+    use crate as nestor;
+    use crate::config::Config;
+    use crate::handler::{Command, CommandHandler, CommandRouter};
+    use crate::request::Request;
+    use crate::{command, routes, Nestor};
+    use state::Container;
+    use std::time::Instant;
+
+    #[command("hresult", aliases = ["hr"])]
+    fn hresult() -> &'static str {
+        "hresult"
+    }
+
+    struct Windows;
+
+    impl Plugin for Windows {
+        type Config = ();
+
+        fn name(&self) -> &'static str {
+            "windows"
+        }
+
+        fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+            routes![hresult]
+        }
+    }
+
+    struct Github;
+
+    impl Plugin for Github {
+        type Config = String;
+
+        fn name(&self) -> &'static str {
+            "github"
+        }
+
+        fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+            Vec::new()
+        }
+    }
+
+    struct Gitlab;
+
+    impl Plugin for Gitlab {
+        type Config = String;
+
+        fn name(&self) -> &'static str {
+            "gitlab"
+        }
+
+        fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+            Vec::new()
+        }
+    }
+
+    fn config() -> Config {
+        toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~"]
+            alias_depth = 2
+        "##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plugins_without_config_can_be_combined() {
+        let nestor = Nestor::with_config(config())
+            .plugin(Windows, PluginConfig::default())
+            .plugin(Github, PluginConfig::default());
+
+        assert_eq!(nestor.routes.len(), 1);
+        assert!(nestor.state.try_get::<()>().is_none());
+        assert!(nestor.state.try_get::<String>().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "plugin 'gitlab'")]
+    fn plugins_cannot_share_a_config_type() {
+        Nestor::with_config(config())
+            .plugin(Github, PluginConfig::default())
+            .plugin(Gitlab, PluginConfig::default());
+    }
+
+    #[test]
+    fn plugin_routes_are_prefixed_and_scoped() {
+        let plugin_config: PluginConfig<()> = toml::de::from_str(
+            r##"
+            prefix = "win"
+            networks = ["libera"]
+            channels = ["#Rust"]
+        "##,
+        )
+        .unwrap();
+        let routes = super::routes(&Windows, &plugin_config);
+        assert_eq!(routes[0].route_id(), Some("win hresult"));
+        assert_eq!(routes[0].aliases(), &["win hr"]);
+
+        let remounted = super::routes(&Windows, &plugin_config);
+        assert!(std::ptr::eq(routes[0].aliases(), remounted[0].aliases()));
+
+        let config = config();
+        let container = Container::new();
+        let router = CommandRouter::new();
+        let mut request = Request {
+            config: &config,
+            command: Command::from_command_str("user", "win hresult").unwrap(),
+            state: &container,
+            router: &router,
+            network: "libera",
+            channel: Some("#rust"),
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+        assert!(routes[0].is_enabled(&request));
+
+        request.channel = None;
+        assert!(!routes[0].is_enabled(&request));
+
+        request.channel = Some("#rust");
+        request.network = "oftc";
+        assert!(!routes[0].is_enabled(&request));
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+
+/// How often keys that have no commands left in their window are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) struct RateLimiter {
+    history: Mutex<History>,
+}
+
+#[derive(Default)]
+struct History {
+    keys: HashMap<String, Window>,
+    swept_at: Option<Instant>,
+}
+
+struct Window {
+    length: Duration,
+    commands: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter {
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    pub fn check(&self, key: &str, limit: RateLimit, now: Instant) -> bool {
+        let length = Duration::from_secs(limit.per_seconds);
+        let mut history = self.history.lock().unwrap();
+
+        let sweep = match history.swept_at {
+            Some(swept_at) => now.duration_since(swept_at) >= SWEEP_INTERVAL,
+            None => true,
+        };
+        if sweep {
+            history.keys.retain(|_, window| {
+                window
+                    .commands
+                    .back()
+                    .map_or(false, |&newest| now.duration_since(newest) < window.length)
+            });
+            history.swept_at = Some(now);
+        }
+
+        let window = history.keys.entry(key.into()).or_insert_with(|| Window {
+            length,
+            commands: VecDeque::new(),
+        });
+        window.length = length;
+
+        while let Some(&oldest) = window.commands.front() {
+            if now.duration_since(oldest) < length {
+                break;
+            }
+            window.commands.pop_front();
+        }
+
+        if window.commands.len() >= limit.commands as usize {
+            return false;
+        }
+
+        window.commands.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RateLimiter;
+    use crate::config::RateLimit;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn limits_within_window() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit {
+            commands: 2,
+            per_seconds: 10,
+        };
+        let start = Instant::now();
+
+        assert!(limiter.check("#rust", limit, start));
+        assert!(limiter.check("#rust", limit, start + Duration::from_secs(1)));
+        assert!(!limiter.check("#rust", limit, start + Duration::from_secs(2)));
+        assert!(limiter.check("#other", limit, start + Duration::from_secs(2)));
+        assert!(limiter.check("#rust", limit, start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn forgets_idle_keys() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit {
+            commands: 2,
+            per_seconds: 10,
+        };
+        let start = Instant::now();
+
+        assert!(limiter.check("#rust", limit, start));
+        assert!(limiter.check("#other", limit, start + Duration::from_secs(55)));
+        assert_eq!(limiter.history.lock().unwrap().keys.len(), 2);
+
+        assert!(limiter.check("#other", limit, start + Duration::from_secs(60)));
+        let history = limiter.history.lock().unwrap();
+        assert!(history.keys.contains_key("#other"));
+        assert!(!history.keys.contains_key("#rust"));
+    }
+}
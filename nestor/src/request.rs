@@ -1,24 +1,36 @@
 use std::ops::Deref;
+use std::time::Instant;
 
-use crate::config::Config;
-use crate::handler::Command;
+use crate::config::{ChannelSettings, Config};
+use crate::handler::{Command, CommandRouter};
 use crate::Nestor;
 
 use anyhow::anyhow;
 use anyhow::Error;
-use irc::client::prelude::Message;
+use chrono::{DateTime, Utc};
+use futures::future::{self, BoxFuture};
+use irc::client::prelude::{Message, Prefix};
 use irc::client::Client;
+use irc::proto::message::Tag;
 use state::Container;
 
 pub struct Request<'r> {
     pub(crate) config: &'r Config,
     pub(crate) command: Command<'r>,
     pub(crate) state: &'r Container,
+    pub(crate) router: &'r CommandRouter,
+    pub(crate) network: &'r str,
+    pub(crate) channel: Option<&'r str>,
+    pub(crate) tags: &'r [Tag],
+    pub(crate) hostmask: Option<String>,
+    pub(crate) received_at: Instant,
 }
 
 impl<'r> Request<'r> {
     pub fn from_message<'c>(
         nestor: &'r Nestor,
+        network: &'r str,
+        config: &'r Config,
         client: &'c Client,
         message: &'r Message,
     ) -> Option<(&'r str, Self)> {
@@ -28,20 +40,71 @@ impl<'r> Request<'r> {
         };
 
         let user = message.source_nickname()?;
+        if config
+            .bot_settings
+            .casemapping
+            .eq(user, client.current_nickname())
+        {
+            return None;
+        }
 
-        let command = Command::try_parse(client.current_nickname(), user, msg, &nestor.config)?;
+        let channel = if default_target.starts_with(&['#', '&'][..]) {
+            Some(default_target.as_str())
+        } else {
+            None
+        };
+
+        let command = Command::try_parse(client.current_nickname(), user, msg, config, channel)?;
 
         let response = message.response_target().unwrap_or(default_target);
+        let hostmask = match &message.prefix {
+            Some(Prefix::Nickname(nick, user, host)) => Some(format!("{}!{}@{}", nick, user, host)),
+            _ => None,
+        };
 
         Some((
             response,
             Request {
-                config: &nestor.config,
+                config,
                 command,
                 state: &nestor.state,
+                router: &nestor.router,
+                network,
+                channel,
+                tags: message.tags.as_deref().unwrap_or(&[]),
+                hostmask,
+                received_at: Instant::now(),
             },
         ))
     }
+
+    pub fn command(&self) -> &Command<'r> {
+        &self.command
+    }
+
+    pub fn command_mut(&mut self) -> &mut Command<'r> {
+        &mut self.command
+    }
+
+    pub fn network(&self) -> &'r str {
+        self.network
+    }
+
+    pub fn channel(&self) -> Option<&'r str> {
+        self.channel
+    }
+
+    pub fn hostmask(&self) -> Option<&str> {
+        self.hostmask.as_deref()
+    }
+
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    pub fn guard<'a, T: FromRequest<'a, 'r>>(&'a self) -> Result<T, T::Error> {
+        T::from_request(self)
+    }
 }
 
 pub struct State<'r, T: Send + Sync + 'static>(&'r T);
@@ -59,11 +122,82 @@ impl<'r, T: Send + Sync + 'static> Deref for State<'r, T> {
     }
 }
 
+pub struct NetworkName<'r>(&'r str);
+
+impl<'r> NetworkName<'r> {
+    pub fn as_str(&self) -> &'r str {
+        self.0
+    }
+}
+
+impl<'r> Deref for NetworkName<'r> {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+pub struct ChannelName<'r>(Option<&'r str>);
+
+impl<'r> ChannelName<'r> {
+    pub fn as_str(&self) -> Option<&'r str> {
+        self.0
+    }
+}
+
+pub struct Hostmask<'a>(Option<&'a str>);
+
+impl<'a> Hostmask<'a> {
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.0
+    }
+}
+
+pub struct Tags<'r>(&'r [Tag]);
+
+impl<'r> Tags<'r> {
+    pub fn get(&self, key: &str) -> Option<&'r str> {
+        self.0
+            .iter()
+            .find(|Tag(name, _)| name == key)
+            .map(|Tag(_, value)| value.as_deref().unwrap_or(""))
+    }
+
+    pub fn msgid(&self) -> Option<&'r str> {
+        self.get("msgid")
+    }
+
+    pub fn account(&self) -> Option<&'r str> {
+        self.get("account")
+    }
+
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        let time = DateTime::parse_from_rfc3339(self.get("time")?).ok()?;
+        Some(time.with_timezone(&Utc))
+    }
+}
+
 pub trait FromRequest<'a, 'r>: Sized {
     type Error;
     fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error>;
 }
 
+pub trait FromRequestAsync<'a, 'r>: Sized {
+    type Error;
+    fn from_request(request: &'a Request<'r>) -> BoxFuture<'a, Result<Self, Self::Error>>;
+}
+
+impl<'a, 'r, T> FromRequestAsync<'a, 'r> for T
+where
+    T: FromRequest<'a, 'r> + Send + 'a,
+    T::Error: Send + 'a,
+{
+    type Error = T::Error;
+    fn from_request(request: &'a Request<'r>) -> BoxFuture<'a, Result<Self, Self::Error>> {
+        Box::pin(future::ready(<T as FromRequest>::from_request(request)))
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for &'a Config {
     type Error = Error;
     fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
@@ -71,6 +205,41 @@ impl<'a, 'r> FromRequest<'a, 'r> for &'a Config {
     }
 }
 
+impl<'a, 'r> FromRequest<'a, 'r> for ChannelSettings<'a> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(request.config.bot_settings.for_channel(request.channel))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for NetworkName<'r> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(NetworkName(request.network))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ChannelName<'r> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(ChannelName(request.channel))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Hostmask<'a> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(Hostmask(request.hostmask()))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Tags<'r> {
+    type Error = Error;
+    fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
+        Ok(Tags(request.tags))
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for &'a Command<'r> {
     type Error = Error;
     fn from_request(request: &'a Request<'r>) -> Result<Self, Self::Error> {
@@ -92,11 +261,13 @@ impl<'a, 'r, T: Send + Sync + 'static> FromRequest<'a, 'r> for State<'r, T> {
 #[cfg(test)]
 mod test {
     use super::State;
+    use crate::handler::CommandRouter;
     use crate::request::FromRequest;
     use crate::Command;
     use crate::Config;
     use crate::Request;
     use state::Container;
+    use std::time::Instant;
 
     #[test]
     fn config_from_request() {
@@ -109,11 +280,18 @@ mod test {
         )
         .unwrap();
         let container = Container::new();
+        let router = CommandRouter::new();
         let command = Command::from_command_str("user", "foo bar baz").unwrap();
         let request = Request {
             config: &config,
             command: command,
             state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
         };
 
         let config = <&Config as FromRequest>::from_request(&request).unwrap();
@@ -121,6 +299,51 @@ mod test {
         assert_eq!(config.bot_settings.command_indicator, ["~", "&&"]);
     }
 
+    #[test]
+    fn channel_settings_from_request() {
+        use crate::config::{ChannelSettings, ReplyMode};
+
+        let config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~", "&&"]
+            alias_depth = 2
+            rate_limit = { commands = 5, per_seconds = 10 }
+
+            [channel_settings."#offtopic"]
+            command_indicator = ["?"]
+            reply_mode = "say"
+        "##,
+        )
+        .unwrap();
+        let container = Container::new();
+        let router = CommandRouter::new();
+        let command = Command::from_command_str("user", "foo bar baz").unwrap();
+        let mut request = Request {
+            config: &config,
+            command,
+            state: &container,
+            router: &router,
+            network: "default",
+            channel: Some("#rust"),
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+
+        let settings = <ChannelSettings as FromRequest>::from_request(&request).unwrap();
+        assert_eq!(settings.command_indicator, ["~", "&&"]);
+        assert_eq!(settings.reply_mode, ReplyMode::Default);
+        assert_eq!(settings.rate_limit.map(|limit| limit.commands), Some(5));
+
+        request.channel = Some("#OffTopic");
+        let settings = <ChannelSettings as FromRequest>::from_request(&request).unwrap();
+        assert_eq!(settings.channel, Some("#OffTopic"));
+        assert_eq!(settings.command_indicator, ["?"]);
+        assert_eq!(settings.reply_mode, ReplyMode::Say);
+        assert_eq!(settings.rate_limit.map(|limit| limit.commands), Some(5));
+    }
+
     #[test]
     fn command_from_request() {
         let config = toml::de::from_str(
@@ -132,11 +355,18 @@ mod test {
         )
         .unwrap();
         let container = Container::new();
+        let router = CommandRouter::new();
         let command = Command::from_command_str("user", "foo bar baz").unwrap();
         let request = Request {
             config: &config,
             command: command,
             state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
         };
 
         let command = <&Command as FromRequest>::from_request(&request).unwrap();
@@ -146,6 +376,82 @@ mod test {
         assert_eq!(command.arguments, ["bar", "baz"]);
     }
 
+    #[test]
+    fn network_from_request() {
+        use super::NetworkName;
+
+        let config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~", "&&"]
+            alias_depth = 2
+        "##,
+        )
+        .unwrap();
+        let container = Container::new();
+        let router = CommandRouter::new();
+        let command = Command::from_command_str("user", "foo bar baz").unwrap();
+        let request = Request {
+            config: &config,
+            command,
+            state: &container,
+            router: &router,
+            network: "libera",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+
+        let network = <NetworkName as FromRequest>::from_request(&request).unwrap();
+
+        assert_eq!(network.as_str(), "libera");
+    }
+
+    #[test]
+    fn tags_from_request() {
+        use super::Tags;
+        use irc::proto::message::Tag;
+
+        let config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~", "&&"]
+            alias_depth = 2
+        "##,
+        )
+        .unwrap();
+        let container = Container::new();
+        let router = CommandRouter::new();
+        let command = Command::from_command_str("user", "foo bar baz").unwrap();
+        let tags = [
+            Tag("msgid".into(), Some("abc123".into())),
+            Tag("account".into(), Some("someone".into())),
+            Tag("time".into(), Some("2020-05-01T12:30:00.000Z".into())),
+        ];
+        let request = Request {
+            config: &config,
+            command,
+            state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &tags,
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+
+        let tags = <Tags as FromRequest>::from_request(&request).unwrap();
+
+        assert_eq!(tags.msgid(), Some("abc123"));
+        assert_eq!(tags.account(), Some("someone"));
+        assert_eq!(tags.get("label"), None);
+        assert_eq!(
+            tags.server_time().map(|time| time.to_rfc3339()),
+            Some("2020-05-01T12:30:00+00:00".into())
+        );
+    }
+
     #[test]
     fn state_from_request_success() {
         let config = toml::de::from_str(
@@ -157,12 +463,19 @@ mod test {
         )
         .unwrap();
         let container = Container::new();
+        let router = CommandRouter::new();
         container.set(42u32);
         let command = Command::from_command_str("user", "foo bar baz").unwrap();
         let request = Request {
             config: &config,
             command: command,
             state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
         };
 
         let state = <State<u32> as FromRequest>::from_request(&request).unwrap();
@@ -181,11 +494,18 @@ mod test {
         )
         .unwrap();
         let container = Container::new();
+        let router = CommandRouter::new();
         let command = Command::from_command_str("user", "foo bar baz").unwrap();
         let request = Request {
             config: &config,
             command: command,
             state: &container,
+            router: &router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
         };
 
         let state = <State<u32> as FromRequest>::from_request(&request);
@@ -12,6 +12,7 @@ pub enum Outcome {
     Success(Response),
     Failure(Error),
     Forward(String),
+    Fallthrough,
 }
 
 pub trait IntoResponse {
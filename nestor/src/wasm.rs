@@ -0,0 +1,577 @@
+//! Commands contributed as WebAssembly modules.
+//!
+//! Every `.wasm` file in the configured directory is loaded when the bot
+//! starts and again on reload. A module registers a command by exporting a
+//! function named `command:<route>` that takes and returns nothing, e.g.
+//! `command:dice` or `command:dice roll`. Modules must also export their
+//! `memory`.
+//!
+//! Modules may import these functions from the `nestor` namespace. Strings
+//! are UTF-8 and passed as a pointer and length into the module's memory.
+//! Functions that copy a string into the module write at most `len` bytes
+//! and return the full length, so a module can retry with a larger buffer.
+//!
+//! - `arg_count() -> i32`
+//! - `arg(index: i32, ptr: i32, len: i32) -> i32`, or -1 if there is no such argument
+//! - `source(ptr: i32, len: i32) -> i32` copies the nickname that sent the command
+//! - `respond(kind: i32, ptr: i32, len: i32)` sets the reply; kind 0 says, 1 acts and 2 notices
+//! - `kv_get(key_ptr: i32, key_len: i32, ptr: i32, len: i32) -> i32`, or -1 if the key is unset
+//! - `kv_set(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32) -> i32`, 0 or -1 if
+//!   the module already stores `max_keys` keys
+//! - `kv_remove(key_ptr: i32, key_len: i32)`
+//!
+//! The key-value store is namespaced by the module's file name, so modules
+//! can't see each other's data. Each invocation gets a fresh instance with a
+//! limited amount of fuel and memory.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::handler::CommandHandler;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct WasmSettings {
+    pub directory: PathBuf,
+    pub fuel: u64,
+    pub memory_limit: usize,
+    pub max_keys: usize,
+}
+
+impl Default for WasmSettings {
+    fn default() -> Self {
+        WasmSettings {
+            directory: PathBuf::from("plugins"),
+            fuel: 10_000_000,
+            memory_limit: 16 * 1024 * 1024,
+            max_keys: 1024,
+        }
+    }
+}
+
+pub trait KvStore: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>>;
+    fn set(&self, namespace: &str, key: &str, value: Option<&str>) -> Result<()>;
+    fn count(&self, namespace: &str) -> Result<usize>;
+}
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<HashMap<(String, String), String>>);
+
+impl KvStore for MemoryStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+        let values = self.0.lock().unwrap();
+        Ok(values.get(&(namespace.into(), key.into())).cloned())
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: Option<&str>) -> Result<()> {
+        let mut values = self.0.lock().unwrap();
+        let key = (namespace.into(), key.into());
+        match value {
+            Some(value) => values.insert(key, value.into()),
+            None => values.remove(&key),
+        };
+
+        Ok(())
+    }
+
+    fn count(&self, namespace: &str) -> Result<usize> {
+        let values = self.0.lock().unwrap();
+        Ok(values.keys().filter(|(name, _)| name == namespace).count())
+    }
+}
+
+pub(crate) struct WasmHost {
+    settings: WasmSettings,
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    store: Arc<dyn KvStore>,
+    #[cfg(feature = "wasm")]
+    runtime: Arc<runtime::Runtime>,
+}
+
+impl WasmHost {
+    pub(crate) fn new(settings: WasmSettings, store: Arc<dyn KvStore>) -> Self {
+        WasmHost {
+            #[cfg(feature = "wasm")]
+            runtime: Arc::new(runtime::Runtime::new(&settings)),
+            settings,
+            store,
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    pub(crate) fn load(&self) -> Result<Vec<Arc<dyn CommandHandler>>> {
+        runtime::load(&self.runtime, &self.settings, &self.store)
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub(crate) fn load(&self) -> Result<Vec<Arc<dyn CommandHandler>>> {
+        println!(
+            "wasm plugins in {} require the `wasm` feature",
+            self.settings.directory.display()
+        );
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod runtime {
+    use std::fs;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+
+    use super::{KvStore, WasmSettings};
+    use crate::handler::{intern, CommandHandler};
+    use crate::request::Request;
+    use crate::response::{Outcome, Response};
+
+    use anyhow::{anyhow, Context, Error, Result};
+    use tokio::task;
+    use wasmi::core::Trap;
+    use wasmi::{
+        Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits,
+        StoreLimitsBuilder,
+    };
+
+    const EXPORT_PREFIX: &str = "command:";
+    const MAX_KEY_LEN: usize = 256;
+    const MAX_VALUE_LEN: usize = 4096;
+    const MAX_RESPONSE_LEN: usize = 1024;
+
+    pub(super) struct Runtime {
+        engine: Engine,
+        linker: Linker<Invocation>,
+        fuel: u64,
+        memory_limit: usize,
+        max_keys: usize,
+    }
+
+    struct Invocation {
+        arguments: Vec<String>,
+        source: String,
+        response: Response,
+        namespace: Arc<str>,
+        store: Arc<dyn KvStore>,
+        max_keys: usize,
+        limits: StoreLimits,
+    }
+
+    impl Runtime {
+        pub(super) fn new(settings: &WasmSettings) -> Self {
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+
+            let mut linker = Linker::new(&engine);
+            define_imports(&mut linker).expect("host imports are defined once");
+
+            Runtime {
+                engine,
+                linker,
+                fuel: settings.fuel,
+                memory_limit: settings.memory_limit,
+                max_keys: settings.max_keys,
+            }
+        }
+    }
+
+    pub(super) fn load(
+        runtime: &Arc<Runtime>,
+        settings: &WasmSettings,
+        store: &Arc<dyn KvStore>,
+    ) -> Result<Vec<Arc<dyn CommandHandler>>> {
+        let mut paths = fs::read_dir(&settings.directory)
+            .with_context(|| {
+                format!(
+                    "unable to read wasm plugins from {}",
+                    settings.directory.display()
+                )
+            })?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().map_or(false, |ext| ext == "wasm"));
+        paths.sort();
+
+        let mut handlers: Vec<Arc<dyn CommandHandler>> = Vec::new();
+        for path in paths {
+            let namespace: Arc<str> = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) => stem.into(),
+                None => continue,
+            };
+            let bytes = fs::read(&path)?;
+            let module = Module::new(&runtime.engine, &bytes[..])
+                .map_err(|err| anyhow!("invalid wasm plugin {}: {}", path.display(), err))?;
+            let module = Arc::new(module);
+
+            for export in module.exports() {
+                let route = match export.name().strip_prefix(EXPORT_PREFIX) {
+                    Some(route) if !route.is_empty() => route,
+                    _ => continue,
+                };
+                match export.ty().func() {
+                    Some(ty) if ty.params().is_empty() && ty.results().is_empty() => {}
+                    _ => {
+                        return Err(anyhow!(
+                            "export '{}' of {} must be a function without parameters or results",
+                            export.name(),
+                            path.display()
+                        ))
+                    }
+                }
+
+                // Route names are interned, so reloading the same modules
+                // doesn't allocate them again.
+                handlers.push(Arc::new(WasmCommand {
+                    runtime: runtime.clone(),
+                    module: module.clone(),
+                    namespace: namespace.clone(),
+                    export: export.name().into(),
+                    route: intern(route),
+                    store: store.clone(),
+                }));
+            }
+        }
+
+        Ok(handlers)
+    }
+
+    #[derive(Clone)]
+    struct WasmCommand {
+        runtime: Arc<Runtime>,
+        module: Arc<Module>,
+        namespace: Arc<str>,
+        export: String,
+        route: &'static str,
+        store: Arc<dyn KvStore>,
+    }
+
+    impl WasmCommand {
+        fn call(&self, arguments: Vec<String>, source: String) -> Result<Response> {
+            let invocation = Invocation {
+                arguments,
+                source,
+                response: Response::None,
+                namespace: self.namespace.clone(),
+                store: self.store.clone(),
+                max_keys: self.runtime.max_keys,
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.runtime.memory_limit)
+                    .instances(1)
+                    .build(),
+            };
+
+            let mut store = Store::new(&self.runtime.engine, invocation);
+            store.limiter(|invocation| &mut invocation.limits);
+            store.add_fuel(self.runtime.fuel).map_err(wasm_error)?;
+
+            let instance = self
+                .runtime
+                .linker
+                .instantiate(&mut store, &self.module)
+                .and_then(|instance| instance.start(&mut store))
+                .map_err(wasm_error)?;
+            instance
+                .get_typed_func::<(), ()>(&store, &self.export)
+                .map_err(wasm_error)?
+                .call(&mut store, ())
+                .map_err(|err| anyhow!("wasm command '{}' failed: {}", self.route, err))?;
+
+            Ok(store.into_data().response)
+        }
+    }
+
+    impl CommandHandler for WasmCommand {
+        fn route_id(&self) -> Option<&'static str> {
+            Some(self.route)
+        }
+
+        fn handle<'a, 'r>(
+            &'a self,
+            request: &'a Request<'r>,
+        ) -> Result<Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>, Error> {
+            // Modules run until they return or run out of fuel, so they get a
+            // thread of their own instead of blocking the runtime.
+            let command = self.clone();
+            let arguments = request.command().arguments.clone();
+            let source = request.command().source_nick.to_string();
+            let call = task::spawn_blocking(move || command.call(arguments, source));
+
+            Ok(Box::pin(async move {
+                match call.await {
+                    Ok(Ok(response)) => Outcome::Success(response),
+                    Ok(Err(err)) => Outcome::Failure(err),
+                    Err(err) => Outcome::Failure(err.into()),
+                }
+            }))
+        }
+    }
+
+    fn wasm_error(err: impl std::fmt::Display) -> Error {
+        anyhow!("{}", err)
+    }
+
+    fn define_imports(linker: &mut Linker<Invocation>) -> Result<(), wasmi::errors::LinkerError> {
+        linker.func_wrap("nestor", "arg_count", |caller: Caller<'_, Invocation>| {
+            caller.data().arguments.len() as i32
+        })?;
+
+        linker.func_wrap(
+            "nestor",
+            "arg",
+            |mut caller: Caller<'_, Invocation>, index: i32, ptr: i32, len: i32| {
+                let argument = match caller.data().arguments.get(index as usize) {
+                    Some(argument) if index >= 0 => argument.clone(),
+                    _ => return Ok(-1),
+                };
+                write(&mut caller, ptr, len, argument.as_bytes())
+            },
+        )?;
+
+        linker.func_wrap(
+            "nestor",
+            "source",
+            |mut caller: Caller<'_, Invocation>, ptr: i32, len: i32| {
+                let source = caller.data().source.clone();
+                write(&mut caller, ptr, len, source.as_bytes())
+            },
+        )?;
+
+        linker.func_wrap(
+            "nestor",
+            "respond",
+            |mut caller: Caller<'_, Invocation>, kind: i32, ptr: i32, len: i32| {
+                let message = read(&caller, ptr, len, MAX_RESPONSE_LEN)?;
+                caller.data_mut().response = match kind {
+                    0 => Response::Say(message),
+                    1 => Response::Act(message),
+                    2 => Response::Notice(message),
+                    _ => return Err(Trap::new(format!("unknown response kind {}", kind))),
+                };
+                Ok(())
+            },
+        )?;
+
+        linker.func_wrap(
+            "nestor",
+            "kv_get",
+            |mut caller: Caller<'_, Invocation>, key_ptr: i32, key_len: i32, ptr: i32, len: i32| {
+                let key = read(&caller, key_ptr, key_len, MAX_KEY_LEN)?;
+                let invocation = caller.data();
+                let value = invocation
+                    .store
+                    .get(&invocation.namespace, &key)
+                    .map_err(|err| Trap::new(err.to_string()))?;
+                match value {
+                    Some(value) => write(&mut caller, ptr, len, value.as_bytes()),
+                    None => Ok(-1),
+                }
+            },
+        )?;
+
+        linker.func_wrap(
+            "nestor",
+            "kv_set",
+            |caller: Caller<'_, Invocation>,
+             key_ptr: i32,
+             key_len: i32,
+             value_ptr: i32,
+             value_len: i32| {
+                let key = read(&caller, key_ptr, key_len, MAX_KEY_LEN)?;
+                let value = read(&caller, value_ptr, value_len, MAX_VALUE_LEN)?;
+                let Invocation {
+                    namespace,
+                    store,
+                    max_keys,
+                    ..
+                } = caller.data();
+                let trap = |err: Error| Trap::new(err.to_string());
+                // Replacing a value is always allowed, only new keys count
+                // against the quota.
+                if store.get(namespace, &key).map_err(trap)?.is_none()
+                    && store.count(namespace).map_err(trap)? >= *max_keys
+                {
+                    return Ok(-1);
+                }
+                store.set(namespace, &key, Some(&value)).map_err(trap)?;
+                Ok(0)
+            },
+        )?;
+
+        linker.func_wrap(
+            "nestor",
+            "kv_remove",
+            |caller: Caller<'_, Invocation>, key_ptr: i32, key_len: i32| {
+                let key = read(&caller, key_ptr, key_len, MAX_KEY_LEN)?;
+                let invocation = caller.data();
+                invocation
+                    .store
+                    .set(&invocation.namespace, &key, None)
+                    .map_err(|err| Trap::new(err.to_string()))
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn memory(caller: &Caller<'_, Invocation>) -> Result<Memory, Trap> {
+        caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| Trap::new("module does not export its memory"))
+    }
+
+    fn read(
+        caller: &Caller<'_, Invocation>,
+        ptr: i32,
+        len: i32,
+        limit: usize,
+    ) -> Result<String, Trap> {
+        if len < 0 || len as usize > limit {
+            return Err(Trap::new(format!(
+                "string length {} exceeds {}",
+                len, limit
+            )));
+        }
+
+        let mut buffer = vec![0; len as usize];
+        memory(caller)?
+            .read(caller, ptr as u32 as usize, &mut buffer)
+            .map_err(|err| Trap::new(err.to_string()))?;
+        String::from_utf8(buffer).map_err(|err| Trap::new(err.to_string()))
+    }
+
+    fn write(
+        caller: &mut Caller<'_, Invocation>,
+        ptr: i32,
+        len: i32,
+        bytes: &[u8],
+    ) -> Result<i32, Trap> {
+        let copied = bytes.len().min(len.max(0) as usize);
+        memory(caller)?
+            .write(caller, ptr as u32 as usize, &bytes[..copied])
+            .map_err(|err| Trap::new(err.to_string()))?;
+        Ok(bytes.len() as i32)
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod test {
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::{KvStore, MemoryStore, WasmHost, WasmSettings};
+    use crate::handler::{Command, CommandRouter};
+    use crate::request::Request;
+    use crate::response::{Outcome, Response};
+
+    const COUNTER: &str = r#"
+        (module
+            (import "nestor" "source" (func $source (param i32 i32) (result i32)))
+            (import "nestor" "respond" (func $respond (param i32 i32 i32)))
+            (import "nestor" "kv_get" (func $kv_get (param i32 i32 i32 i32) (result i32)))
+            (import "nestor" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "count")
+            (data (i32.const 8) "other")
+            (data (i32.const 96) "full")
+            (func (export "command:count")
+                (if (i32.lt_s (call $kv_get (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 1)) (i32.const 0))
+                    (then (i32.store8 (i32.const 16) (i32.const 48))))
+                (i32.store8 (i32.const 16) (i32.add (i32.load8_u (i32.const 16)) (i32.const 1)))
+                (drop (call $kv_set (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 1)))
+                (call $respond (i32.const 0) (i32.const 16) (i32.const 1)))
+            (func (export "command:other")
+                (if (i32.lt_s (call $kv_set (i32.const 8) (i32.const 5) (i32.const 16) (i32.const 1)) (i32.const 0))
+                    (then (call $respond (i32.const 0) (i32.const 96) (i32.const 4)))))
+            (func (export "command:whoami")
+                (call $respond (i32.const 2) (i32.const 32)
+                    (call $source (i32.const 32) (i32.const 64))))
+            (func (export "command:spin")
+                (loop $forever (br $forever))))
+    "#;
+
+    fn dispatch(router: &CommandRouter, command: &str) -> Outcome {
+        let config = toml::de::from_str(
+            r##"
+            blacklisted_users = []
+            command_indicator = ["~"]
+            alias_depth = 2
+        "##,
+        )
+        .unwrap();
+        let container = state::Container::new();
+        let request = Request {
+            config: &config,
+            command: Command::from_command_str("user", command).unwrap(),
+            state: &container,
+            router,
+            network: "default",
+            channel: None,
+            tags: &[],
+            hostmask: None,
+            received_at: Instant::now(),
+        };
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(router.route(&request))
+    }
+
+    #[test]
+    fn runs_wasm_commands() {
+        let directory = std::env::temp_dir().join(format!("nestor-wasm-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("counter.wasm"),
+            wat::parse_str(COUNTER).unwrap(),
+        )
+        .unwrap();
+
+        let store = Arc::new(MemoryStore::default());
+        let host = WasmHost::new(
+            WasmSettings {
+                directory: directory.clone(),
+                fuel: 100_000,
+                max_keys: 1,
+                ..WasmSettings::default()
+            },
+            store.clone(),
+        );
+        let router = CommandRouter::new();
+        router.set_handlers(host.load().unwrap()).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        for expected in &["1", "2"] {
+            match dispatch(&router, "count") {
+                Outcome::Success(res) => assert_eq!(res, Response::Say(expected.to_string())),
+                _ => panic!("unexpected outcome"),
+            }
+        }
+        assert_eq!(store.get("counter", "count").unwrap(), Some("2".into()));
+
+        match dispatch(&router, "other") {
+            Outcome::Success(res) => assert_eq!(res, Response::Say("full".into())),
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(store.get("counter", "other").unwrap(), None);
+
+        match dispatch(&router, "whoami") {
+            Outcome::Success(res) => assert_eq!(res, Response::Notice("user".into())),
+            _ => panic!("unexpected outcome"),
+        }
+
+        match dispatch(&router, "spin") {
+            Outcome::Failure(_) => {}
+            _ => panic!("expected the command to run out of fuel"),
+        }
+    }
+}
@@ -174,7 +174,7 @@ fn setup(port: u16) -> TcpStream {
         );
         let config = toml::de::from_str(&config_str).unwrap();
         let _ = std::panic::catch_unwind(|| {
-            Nestor::with_config(config).activate();
+            Nestor::with_config(config).discover_routes().activate();
         });
     });
 
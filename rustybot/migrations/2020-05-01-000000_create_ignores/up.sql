@@ -0,0 +1,7 @@
+CREATE TABLE ignores (
+    id INTEGER PRIMARY KEY NOT NULL,
+    mask TEXT NOT NULL UNIQUE,
+    expires_at BIGINT,
+    reason TEXT,
+    added_by TEXT NOT NULL
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE plugin_values
\ No newline at end of file
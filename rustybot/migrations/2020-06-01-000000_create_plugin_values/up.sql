@@ -0,0 +1,7 @@
+CREATE TABLE plugin_values (
+    id INTEGER PRIMARY KEY NOT NULL,
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    UNIQUE (namespace, key)
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TRIGGER factoid_search_insert;
+DROP TABLE factoid_search;
\ No newline at end of file
@@ -0,0 +1,14 @@
+CREATE VIRTUAL TABLE factoid_search USING fts5(label, description);
+
+CREATE TRIGGER factoid_search_insert AFTER INSERT ON factoids
+WHEN NEW.id = (SELECT id FROM factoids WHERE label = NEW.label ORDER BY timestamp DESC, id DESC LIMIT 1)
+BEGIN
+    DELETE FROM factoid_search WHERE label = NEW.label;
+    INSERT INTO factoid_search (label, description)
+        SELECT NEW.label, NEW.description WHERE NEW.intent != 'forget';
+END;
+
+INSERT INTO factoid_search (label, description)
+    SELECT f.label, f.description FROM factoids f
+    WHERE f.id = (SELECT id FROM factoids WHERE label = f.label ORDER BY timestamp DESC, id DESC LIMIT 1)
+    AND f.intent != 'forget';
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE factoid_hits
\ No newline at end of file
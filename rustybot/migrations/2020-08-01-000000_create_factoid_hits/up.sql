@@ -0,0 +1,10 @@
+CREATE TABLE factoid_hits (
+    id INTEGER PRIMARY KEY NOT NULL,
+    label TEXT NOT NULL,
+    nickname TEXT NOT NULL,
+    channel TEXT,
+    timestamp DATETIME NOT NULL
+);
+
+CREATE INDEX factoid_hits_label ON factoid_hits (label, timestamp);
+CREATE INDEX factoid_hits_timestamp ON factoid_hits (timestamp);
\ No newline at end of file
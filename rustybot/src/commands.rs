@@ -1,10 +1,98 @@
+use crate::config::GithubAuth;
+
+use nestor::handler::CommandHandler;
+use nestor::plugin::Plugin;
+use nestor::routes;
+
 mod crate_info;
 mod default;
+pub mod duration;
 mod factoid_metadata;
 mod forget;
 mod github;
+mod history;
+mod ignore;
 mod learn;
 mod lock;
+mod placeholder;
 mod qotd;
+mod reload;
 mod rustc_error;
+mod search;
+mod stats;
 mod windows_error;
+
+pub fn routes() -> Vec<Box<dyn CommandHandler>> {
+    routes![
+        crate_info::crate_info,
+        ignore::ignore,
+        ignore::unignore,
+        ignore::ignores,
+        qotd::qotd,
+        qotd::qotd_add,
+        reload::reload,
+        rustc_error::rustc_error,
+    ]
+}
+
+pub struct Factoids;
+
+impl Plugin for Factoids {
+    type Config = ();
+
+    fn name(&self) -> &'static str {
+        "factoids"
+    }
+
+    fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+        routes![
+            default::user_defined,
+            default::suggestion,
+            factoid_metadata::metadata,
+            forget::forget,
+            history::history,
+            history::diff,
+            history::revert,
+            learn::learn,
+            lock::lock,
+            lock::unlock,
+            placeholder::preview,
+            search::search,
+            search::search_content,
+            stats::factoid_stats,
+            stats::top_factoids,
+        ]
+    }
+}
+
+pub struct WindowsErrors;
+
+impl Plugin for WindowsErrors {
+    type Config = ();
+
+    fn name(&self) -> &'static str {
+        "windows"
+    }
+
+    fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+        routes![
+            windows_error::hresult,
+            windows_error::nt_status,
+            windows_error::win32,
+        ]
+    }
+}
+
+pub struct Github;
+
+impl Plugin for Github {
+    type Config = GithubAuth;
+
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    fn routes(&self) -> Vec<Box<dyn CommandHandler>> {
+        routes![github::rfc]
+    }
+}
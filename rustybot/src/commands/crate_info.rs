@@ -1,11 +1,13 @@
 use std::ops::Deref;
+use std::time::Instant;
 
-use crate::config::RustybotSettings;
+use crate::config::Settings;
 
 use anyhow::Result;
 use nestor::command;
 use nestor::config::Config as NestorConfig;
 use nestor::handler::Command;
+use nestor::metrics::Metrics;
 use nestor::request::State;
 use reqwest::header::USER_AGENT;
 use reqwest::Client;
@@ -26,17 +28,20 @@ struct Crate {
     documentation: Option<String>,
 }
 
-#[command("crate")]
+#[command("crate", timeout = 15)]
 pub async fn crate_info<'a>(
     command: &'a Command<'a>,
     nestor_config: &'a NestorConfig,
-    r_config: State<'a, RustybotSettings>,
+    r_config: State<'a, Settings>,
+    metrics: State<'a, Metrics>,
 ) -> Result<String> {
+    let r_config = r_config.get();
     if command.arguments.len() != 1 {
         return Ok("Invalid command format, please use ~crate <crate>".into());
     }
 
     let client = Client::builder().build()?;
+    let started = Instant::now();
     let response = client
         .get(&format!(
             "https://crates.io/api/v1/crates/{}",
@@ -56,7 +61,13 @@ pub async fn crate_info<'a>(
             ),
         )
         .send()
-        .await?;
+        .await;
+    metrics.observe(
+        "rustybot_http_request_duration_seconds",
+        &[("service", "crates.io")],
+        started.elapsed().as_secs_f64(),
+    );
+    let response = response?;
 
     match response.status() {
         StatusCode::OK => {
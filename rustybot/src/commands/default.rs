@@ -1,35 +1,83 @@
-use crate::database::models::FactoidEnum;
+use super::placeholder;
+use crate::config::Settings;
+use crate::database::models::{Factoid, FactoidEnum};
 use crate::database::Db;
+use crate::script;
 
+use anyhow::Result;
 use nestor::command;
-use nestor::handler::Command;
-use nestor::request::State;
+use nestor::config::Config;
+use nestor::handler::{max_suggestion_distance, suggest, Command, Routes};
+use nestor::metrics::Metrics;
+use nestor::request::{ChannelName, State};
 use nestor::response::{Outcome, Response};
 
 #[command]
-pub fn user_defined(command: &Command, db: State<Db>) -> Outcome {
-    let num_args = command.arguments.len();
-
+pub async fn user_defined<'a>(
+    command: &'a Command<'a>,
+    channel: ChannelName<'a>,
+    nestor_config: &'a Config,
+    config: State<'a, Settings>,
+    db: State<'a, Db>,
+    metrics: State<'a, Metrics>,
+) -> Outcome {
     let full_command: Vec<_> = std::iter::once(&command.command_str)
         .chain(command.arguments.as_slice())
         .map(|s| s.as_ref())
         .collect();
 
-    let (name, label) = if full_command.len() > 2 && full_command[full_command.len() - 2] == "@" {
+    let (mut name, words) = if full_command.len() > 2 && full_command[full_command.len() - 2] == "@"
+    {
         (
             Some(full_command[full_command.len() - 1]),
-            full_command[0..full_command.len() - 2].join(" "),
+            &full_command[0..full_command.len() - 2],
         )
     } else {
-        (None, full_command.join(" "))
+        (None, &full_command[..])
     };
+    let label = words.join(" ");
 
     println!("command is: '{}'", label);
-    let response = match db.get_factoid(&label) {
-        Ok(Some(factoid)) => match factoid.intent {
-            FactoidEnum::Forget => {
-                Response::Notice(format!("unknown factoid '{}'", command.command_str))
+    let bot_settings = &nestor_config.bot_settings;
+    let lookup = |key: &str| {
+        if bot_settings.case_insensitive_commands {
+            db.find_factoid(key, bot_settings.casemapping)
+        } else {
+            db.get_factoid(key)
+        }
+    };
+
+    let mut arguments = &words[..0];
+    let mut factoid = lookup(&label);
+
+    // Factoids that take arguments are looked up by their first word, the rest
+    // are passed to them as arguments.
+    if words.len() > 1 && factoid.as_ref().map_or(false, Option::is_none) {
+        if let Ok(Some(parameterised)) = lookup(words[0]) {
+            if takes_arguments(&parameterised) {
+                factoid = Ok(Some(parameterised));
+                arguments = &words[1..];
+            }
+        }
+    }
+
+    let result = match &factoid {
+        Ok(Some(factoid)) if factoid.intent != FactoidEnum::Forget => {
+            if let Err(err) =
+                db.record_factoid_hit(&factoid.label, command.source_nick, channel.as_str())
+            {
+                println!("failed to record hit for '{}': {:?}", factoid.label, err);
             }
+            "hit"
+        }
+        Ok(_) => "miss",
+        Err(_) => "error",
+    };
+    metrics.increment("rustybot_factoid_lookups_total", &[("result", result)]);
+
+    let response = match factoid {
+        Ok(Some(factoid)) => match factoid.intent {
+            FactoidEnum::Forget => return Outcome::Fallthrough,
             FactoidEnum::Alias => {
                 if let Some(name) = name {
                     return Outcome::Forward(format!("{} @ {}", factoid.description, name));
@@ -37,12 +85,41 @@ pub fn user_defined(command: &Command, db: State<Db>) -> Outcome {
                     return Outcome::Forward(factoid.description);
                 }
             }
-            _ => factoid.intent.to_response(factoid.description),
+            FactoidEnum::Script => {
+                let context = script::Context {
+                    nick: command.source_nick.into(),
+                    channel: channel.as_str().map(String::from),
+                    arguments: arguments.iter().map(|arg| arg.to_string()).collect(),
+                };
+                let settings = config.get().scripts;
+                match script::run_blocking(factoid.description, context, db.clone(), settings).await
+                {
+                    Ok(output) if output.is_empty() => Response::None,
+                    Ok(output) => Response::Say(output),
+                    Err(err) => {
+                        Response::Notice(format!("factoid '{}' failed: {}", factoid.label, err))
+                    }
+                }
+            }
+            _ => {
+                // A factoid that names its target doesn't need to be prefixed
+                // with it.
+                if placeholder::mentions_who(&factoid.description) {
+                    name = None;
+                }
+
+                let context = placeholder::Context {
+                    nick: command.source_nick,
+                    who: name,
+                    channel: channel.as_str(),
+                    arguments,
+                };
+                factoid
+                    .intent
+                    .to_response(placeholder::expand(&factoid.description, &context))
+            }
         },
-        Ok(None) if num_args == 0 => {
-            Response::Notice(format!("unknown factoid '{}'", command.command_str))
-        }
-        Ok(None) => Response::None,
+        Ok(None) => return Outcome::Fallthrough,
         Err(err) => return Outcome::Failure(err.into()),
     };
 
@@ -60,3 +137,48 @@ pub fn user_defined(command: &Command, db: State<Db>) -> Outcome {
         }
     })
 }
+
+fn takes_arguments(factoid: &Factoid) -> bool {
+    match factoid.intent {
+        FactoidEnum::Script => true,
+        FactoidEnum::Say | FactoidEnum::Act => placeholder::takes_arguments(&factoid.description),
+        FactoidEnum::Alias | FactoidEnum::Forget => false,
+    }
+}
+
+#[command(rank = 10)]
+pub fn suggestion(
+    command: &Command,
+    config: &Config,
+    routes: Routes,
+    db: State<Db>,
+) -> Result<Response> {
+    if !command.arguments.is_empty() {
+        return Ok(Response::None);
+    }
+
+    let name = &command.command_str;
+    let length = name.chars().count();
+    let distance = max_suggestion_distance(name);
+    let labels = db.factoid_labels(length.saturating_sub(distance)..=length + distance)?;
+    let mut suggestions = routes.suggest(name);
+    for factoid in suggest(
+        config.bot_settings.casemapping,
+        name,
+        labels.iter().map(String::as_str),
+    ) {
+        if !suggestions.contains(&factoid) {
+            suggestions.push(factoid);
+        }
+    }
+
+    Ok(if suggestions.is_empty() {
+        Response::Notice(format!("unknown factoid '{}'", name))
+    } else {
+        Response::Notice(format!(
+            "unknown factoid '{}', did you mean: {}?",
+            name,
+            suggestions.join(", ")
+        ))
+    })
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::naive::NaiveDateTime;
+use chrono::offset::Utc;
+
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let split = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = duration.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+
+    amount.checked_mul(seconds).map(Duration::from_secs)
+}
+
+/// The point in time `duration` ago, if it can be represented.
+pub fn since(duration: Duration) -> Option<NaiveDateTime> {
+    let duration = chrono::Duration::from_std(duration).ok()?;
+    Utc::now().naive_utc().checked_sub_signed(duration)
+}
+
+/// The point in time `duration` from now, if it can be represented.
+pub fn until(duration: Duration) -> Option<SystemTime> {
+    SystemTime::now().checked_add(duration)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_duration, since, until};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(
+            parse_duration("2w"),
+            Some(Duration::from_secs(2 * 7 * 86400))
+        );
+        assert_eq!(parse_duration("3y"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("5"), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+        assert_eq!(parse_duration("9999999999999999w"), None);
+
+        assert!(since(Duration::from_secs(86400)).is_some());
+        assert_eq!(since(parse_duration("9999999999w").unwrap()), None);
+        assert_eq!(since(Duration::from_secs(u64::MAX)), None);
+    }
+
+    #[test]
+    fn rejects_durations_past_the_end_of_time() {
+        assert!(until(Duration::from_secs(86400)).is_some());
+        assert_eq!(
+            until(parse_duration("10000000000000000000s").unwrap()),
+            None
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::{is_admin, RustybotSettings};
+use crate::config::{is_admin, Settings};
 use crate::database::models::FactoidEnum;
 use crate::database::Db;
 
@@ -8,7 +8,7 @@ use nestor::handler::Command;
 use nestor::request::State;
 
 #[command("forget")]
-pub fn forget(command: &Command, config: State<RustybotSettings>, db: State<Db>) -> Result<String> {
+pub fn forget(command: &Command, config: State<Settings>, db: State<Db>) -> Result<String> {
     if command.arguments.is_empty() {
         return Ok("Invalid command format, please use ~forget <factoid>".into());
     }
@@ -17,7 +17,7 @@ pub fn forget(command: &Command, config: State<RustybotSettings>, db: State<Db>)
     Ok(match db.get_factoid(&actual_factoid)? {
         Some(ref factoid)
             if factoid.intent != FactoidEnum::Forget
-                && (!factoid.locked || is_admin(command.source_nick, &config)) =>
+                && (!factoid.locked || is_admin(command.source_nick, &config.get())) =>
         {
             db.create_factoid(
                 command.source_nick,
@@ -1,15 +1,17 @@
-use crate::config::RustybotSettings;
+use crate::config::{GithubAuth, Settings};
 
 use anyhow::Result;
 use nestor::command;
 use nestor::config::Config;
 use nestor::handler::Command;
+use nestor::metrics::Metrics;
 use nestor::request::State;
 use reqwest::header::{ACCEPT, USER_AGENT};
 use reqwest::Client;
 use reqwest::StatusCode;
 use serde::Deserialize;
 use std::ops::Deref;
+use std::time::Instant;
 
 #[derive(Deserialize)]
 struct PullRequest {
@@ -20,12 +22,15 @@ struct PullRequest {
     pub merged: bool,
 }
 
-#[command("rfc")]
+#[command("rfc", timeout = 15)]
 pub async fn rfc<'a>(
     command: &'a Command<'a>,
     config: &'a Config,
-    r_config: State<'a, RustybotSettings>,
+    r_config: State<'a, Settings>,
+    github_auth: State<'a, GithubAuth>,
+    metrics: State<'a, Metrics>,
 ) -> Result<String> {
+    let r_config = r_config.get();
     let rfc = match command.arguments.get(0).map(|arg| arg.parse::<u32>()) {
         Some(Ok(rfc)) => rfc,
         Some(Err(_)) => return Ok("RFC must be a number.".into()),
@@ -35,15 +40,13 @@ pub async fn rfc<'a>(
     };
 
     let client = Client::builder().build()?;
+    let started = Instant::now();
     let response = client
         .get(&format!(
             "https://api.github.com/repos/rust-lang/rfcs/pulls/{}",
             rfc
         ))
-        .basic_auth(
-            &r_config.github_auth.username,
-            Some(&r_config.github_auth.password),
-        )
+        .basic_auth(&github_auth.username, Some(&github_auth.password))
         .header(ACCEPT, "application/vnd.github.v3+json")
         .header(
             USER_AGENT,
@@ -59,7 +62,13 @@ pub async fn rfc<'a>(
             ),
         )
         .send()
-        .await?;
+        .await;
+    metrics.observe(
+        "rustybot_http_request_duration_seconds",
+        &[("service", "github")],
+        started.elapsed().as_secs_f64(),
+    );
+    let response = response?;
 
     match response.status() {
         StatusCode::OK => {
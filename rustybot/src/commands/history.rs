@@ -0,0 +1,195 @@
+use crate::config::{is_admin, Settings};
+use crate::database::models::{Factoid, FactoidEnum};
+use crate::database::Db;
+
+use anyhow::Result;
+use nestor::command;
+use nestor::handler::Command;
+use nestor::request::State;
+
+const PAGE_SIZE: i64 = 5;
+
+#[command("factoid-history")]
+pub fn history(command: &Command, db: State<Db>) -> Result<String> {
+    let (label, page) = match split_number(&command.arguments) {
+        Some((label, page)) => (label, page),
+        None if !command.arguments.is_empty() => (command.arguments.join(" "), 1),
+        None => {
+            return Ok(
+                "Invalid command format, please use ~factoid-history <factoid> [page]".into(),
+            )
+        }
+    };
+
+    let count = db.factoid_revision_count(&label)?;
+    if count == 0 {
+        return Ok(format!("Factoid '{}' does not exist", label));
+    }
+
+    let pages = (count + PAGE_SIZE - 1) / PAGE_SIZE;
+    if page < 1 || page > pages {
+        return Ok(format!(
+            "Factoid '{}' only has {} page(s) of history",
+            label, pages
+        ));
+    }
+
+    let offset = (page - 1) * PAGE_SIZE;
+    let revisions = db.factoid_history(&label, offset, PAGE_SIZE)?;
+    let entries: Vec<_> = revisions
+        .iter()
+        .zip((1..=count - offset).rev())
+        .map(|(factoid, revision)| {
+            format!(
+                "#{} {} by {} on {}",
+                revision,
+                describe(factoid),
+                factoid.nickname,
+                factoid.timestamp.format("%Y-%m-%d %H:%M")
+            )
+        })
+        .collect();
+
+    Ok(format!(
+        "History for '{}' (page {}/{}): {}",
+        label,
+        page,
+        pages,
+        entries.join("; ")
+    ))
+}
+
+#[command("factoid-diff")]
+pub fn diff(command: &Command, db: State<Db>) -> Result<String> {
+    let (label, revision) = match split_number(&command.arguments) {
+        Some(arguments) => arguments,
+        None => {
+            return Ok(
+                "Invalid command format, please use ~factoid-diff <factoid> <revision>".into(),
+            )
+        }
+    };
+
+    let factoid = match db.factoid_revision(&label, revision)? {
+        Some(factoid) => factoid,
+        None => return Ok(format!("Factoid '{}' has no revision #{}", label, revision)),
+    };
+
+    let previous = db.factoid_revision(&label, revision - 1)?;
+    let mut changes = Vec::new();
+    match &previous {
+        Some(previous) => {
+            if previous.intent != factoid.intent {
+                changes.push(format!("{} -> {}", describe(previous), describe(&factoid)));
+            } else if previous.locked != factoid.locked {
+                changes.push(describe(&factoid).into());
+            }
+            if previous.description != factoid.description {
+                changes.push(diff_words(&previous.description, &factoid.description));
+            }
+        }
+        None => changes.push(format!("created: {}", factoid.description)),
+    }
+
+    if changes.is_empty() {
+        changes.push("no changes".into());
+    }
+
+    Ok(format!(
+        "'{}' #{} by {} on {}: {}",
+        label,
+        revision,
+        factoid.nickname,
+        factoid.timestamp.format("%Y-%m-%d %H:%M"),
+        changes.join("; ")
+    ))
+}
+
+#[command("revert")]
+pub fn revert(command: &Command, config: State<Settings>, db: State<Db>) -> Result<String> {
+    if !is_admin(command.source_nick, &config.get()) {
+        return Ok("Only an admin can revert a factoid".into());
+    }
+
+    let (label, revision) = match split_number(&command.arguments) {
+        Some(arguments) => arguments,
+        None => {
+            return Ok("Invalid command format, please use ~revert <factoid> <revision>".into())
+        }
+    };
+
+    let old = match db.factoid_revision(&label, revision)? {
+        Some(factoid) => factoid,
+        None => return Ok(format!("Factoid '{}' has no revision #{}", label, revision)),
+    };
+
+    // Reverting restores the content of a revision but keeps the current lock.
+    let locked = db
+        .get_factoid(&label)?
+        .map_or(old.locked, |factoid| factoid.locked);
+    db.create_factoid(
+        command.source_nick,
+        old.intent,
+        &old.label,
+        &old.description,
+        locked,
+    )?;
+
+    Ok(format!("reverted factoid '{}' to #{}", old.label, revision))
+}
+
+/// Splits a trailing revision or page number off a factoid label.
+fn split_number(arguments: &[String]) -> Option<(String, i64)> {
+    match arguments.split_last() {
+        Some((number, label)) if !label.is_empty() => {
+            number.parse().ok().map(|number| (label.join(" "), number))
+        }
+        _ => None,
+    }
+}
+
+fn describe(factoid: &Factoid) -> &'static str {
+    match (&factoid.intent, factoid.locked) {
+        (FactoidEnum::Forget, _) => "forgotten",
+        (FactoidEnum::Alias, false) => "alias",
+        (FactoidEnum::Alias, true) => "alias (locked)",
+        (FactoidEnum::Act, false) => "act",
+        (FactoidEnum::Act, true) => "act (locked)",
+        (FactoidEnum::Script, false) => "script",
+        (FactoidEnum::Script, true) => "script (locked)",
+        (FactoidEnum::Say, false) => "say",
+        (FactoidEnum::Say, true) => "say (locked)",
+    }
+}
+
+/// Marks the words that changed between two descriptions, keeping the common
+/// words at either end.
+fn diff_words(old: &str, new: &str) -> String {
+    let old: Vec<_> = old.split_whitespace().collect();
+    let new: Vec<_> = new.split_whitespace().collect();
+
+    let prefix = old
+        .iter()
+        .zip(&new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let mut words: Vec<String> = old[..prefix].iter().map(|&word| word.into()).collect();
+    let removed = &old[prefix..old.len() - suffix];
+    let added = &new[prefix..new.len() - suffix];
+    if !removed.is_empty() {
+        words.push(format!("[-{}-]", removed.join(" ")));
+    }
+    if !added.is_empty() {
+        words.push(format!("{{+{}+}}", added.join(" ")));
+    }
+    words.extend(old[old.len() - suffix..].iter().map(|&word| word.into()));
+
+    words.join(" ")
+}
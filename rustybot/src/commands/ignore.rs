@@ -0,0 +1,111 @@
+use std::time::SystemTime;
+
+use super::duration::{parse_duration, until};
+use crate::config::{is_admin, Settings};
+
+use anyhow::Result;
+use nestor::command;
+use nestor::config::Config;
+use nestor::handler::Command;
+use nestor::ignore::{unix_time, IgnoreList, IgnoreRule};
+use nestor::request::{Hostmask, State};
+
+#[command("ignore")]
+pub fn ignore(
+    command: &Command,
+    hostmask: Hostmask,
+    nestor_config: &Config,
+    config: State<Settings>,
+    ignores: State<IgnoreList>,
+) -> Result<String> {
+    if !is_admin(command.source_nick, &config.get()) {
+        return Ok("Only an admin can ignore users".into());
+    }
+
+    let usage = "Invalid command format, please use ~ignore <nick|hostmask> [duration] [reason]";
+    let mut args = command.arguments.iter();
+    let mask = match args.next() {
+        Some(mask) => mask,
+        None => return Ok(usage.into()),
+    };
+
+    let mut rule = IgnoreRule::new(mask, command.source_nick);
+    let caller = match hostmask.as_str() {
+        Some(hostmask) => hostmask.to_string(),
+        None => format!("{}!@", command.source_nick),
+    };
+    if rule.matches(&caller, nestor_config.bot_settings.casemapping) {
+        return Ok(format!("'{}' would ignore you as well", rule.mask));
+    }
+
+    let mut reason: Vec<&str> = args.map(String::as_str).collect();
+    if let Some(duration) = reason.first().and_then(|arg| parse_duration(arg)) {
+        match until(duration) {
+            Some(expires_at) => rule.expires_at = Some(unix_time(expires_at)),
+            None => return Ok(usage.into()),
+        }
+        reason.remove(0);
+    }
+
+    if !reason.is_empty() {
+        rule.reason = Some(reason.join(" "));
+    }
+
+    let response = match rule.expires_at {
+        Some(_) => format!("ignoring '{}' for {}", rule.mask, command.arguments[1]),
+        None => format!("ignoring '{}'", rule.mask),
+    };
+    ignores.add(rule)?;
+
+    Ok(response)
+}
+
+#[command("unignore")]
+pub fn unignore(
+    command: &Command,
+    config: State<Settings>,
+    ignores: State<IgnoreList>,
+) -> Result<String> {
+    if !is_admin(command.source_nick, &config.get()) {
+        return Ok("Only an admin can unignore users".into());
+    }
+
+    if command.arguments.len() != 1 {
+        return Ok("Invalid command format, please use ~unignore <nick|hostmask>".into());
+    }
+
+    Ok(if ignores.remove(&command.arguments[0])? {
+        format!("no longer ignoring '{}'", command.arguments[0])
+    } else {
+        format!("'{}' is not ignored", command.arguments[0])
+    })
+}
+
+#[command("ignores")]
+pub fn ignores(command: &Command, config: State<Settings>, ignores: State<IgnoreList>) -> String {
+    if !is_admin(command.source_nick, &config.get()) {
+        return "Only an admin can list ignored users".into();
+    }
+
+    let now = unix_time(SystemTime::now());
+    let rules: Vec<_> = ignores
+        .list()
+        .into_iter()
+        .map(|rule| {
+            let mut entry = rule.mask;
+            if let Some(expires_at) = rule.expires_at {
+                entry += &format!(" ({}m left)", (expires_at.saturating_sub(now) + 59) / 60);
+            }
+            if let Some(reason) = rule.reason {
+                entry += &format!(" [{}]", reason);
+            }
+            entry
+        })
+        .collect();
+
+    if rules.is_empty() {
+        "no users are ignored".into()
+    } else {
+        format!("ignored: {}", rules.join(", "))
+    }
+}
@@ -1,17 +1,20 @@
 use std::marker::PhantomData;
 use std::ops::Deref;
 
-use crate::config::{is_admin, RustybotSettings};
+use crate::config::{is_admin, RustybotSettings, Settings};
 use crate::database::models::{Factoid, FactoidEnum};
 use crate::database::Db;
+use crate::script;
 
 use anyhow::Result;
 use nestor::command;
 use nestor::handler::Command;
 use nestor::request::State;
+use regex::{Regex, RegexBuilder};
 
 #[command("learn")]
-pub fn learn(command: &Command, config: State<RustybotSettings>, db: State<Db>) -> Result<String> {
+pub fn learn(command: &Command, config: State<Settings>, db: State<Db>) -> Result<String> {
+    let config = config.get();
     let operation_index = match command
         .arguments
         .iter()
@@ -23,6 +26,7 @@ pub fn learn(command: &Command, config: State<RustybotSettings>, db: State<Db>)
                 || **arg == "f="
                 || **arg == "!="
                 || **arg == "@="
+                || **arg == "$="
                 || **arg == "~="
         })
         .map(|(idx, _)| idx)
@@ -121,11 +125,191 @@ pub fn learn(command: &Command, config: State<RustybotSettings>, db: State<Db>)
                 PhantomData::<fn(&Factoid) -> String>,
             ),
         )?,
-        format @ "~=" => format!("learn format {} is currently unimplemented", format),
+        "$=" => match script::check(raw_description.trim()) {
+            Ok(()) => learn_helper(
+                command.source_nick,
+                &actual_factoid,
+                &config,
+                existing_factoid,
+                &db,
+                FactoidEnum::Script,
+                EditOptions::MustNot(
+                    || raw_description.trim(),
+                    PhantomData::<fn(&Factoid) -> String>,
+                ),
+            )?,
+            Err(err) => format!("cannot learn '{}': {}", actual_factoid, err),
+        },
+        "~=" => {
+            let substitution = match Substitution::parse(raw_description.trim()) {
+                Ok(substitution) => substitution,
+                Err(err) => return Ok(err),
+            };
+            let edited = match existing_factoid
+                .as_ref()
+                .filter(|factoid| factoid.intent != FactoidEnum::Forget)
+            {
+                Some(factoid) if factoid.locked && !is_admin(command.source_nick, &config) => {
+                    return Ok(format!(
+                        "cannot edit factoid: '{}' because it is locked.",
+                        actual_factoid
+                    ));
+                }
+                Some(factoid) => match substitution.apply(&factoid.description) {
+                    Ok(description) => description,
+                    Err(err) => return Ok(format!("cannot edit '{}': {}", actual_factoid, err)),
+                },
+                None => String::new(),
+            };
+
+            learn_helper(
+                command.source_nick,
+                &actual_factoid,
+                &config,
+                existing_factoid,
+                &db,
+                FactoidEnum::Say,
+                EditOptions::Must(|_: &Factoid| edited.as_str(), PhantomData::<fn() -> String>),
+            )?
+        }
         _ => "Invalid command format, please use ~learn <factoid> = <description>".into(),
     })
 }
 
+const MAX_PATTERN_LEN: usize = 256;
+const MAX_REGEX_SIZE: usize = 1 << 20;
+const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// A sed-style `s/pattern/replacement/flags` edit. Any punctuation can be used
+/// as the delimiter, `\N` in the replacement refers to capture group N, and
+/// the flags are `g` (replace every match) and `i` (ignore case).
+struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    fn parse(expression: &str) -> Result<Self, String> {
+        let usage =
+            "Invalid substitution, please use ~learn <factoid> ~= s/<pattern>/<replacement>/[gi]";
+        let mut chars = expression.chars();
+        let delimiter = match (chars.next(), chars.next()) {
+            (Some('s'), Some(delimiter))
+                if !delimiter.is_alphanumeric()
+                    && !delimiter.is_whitespace()
+                    && delimiter != '\\' =>
+            {
+                delimiter
+            }
+            _ => return Err(usage.into()),
+        };
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                // Escapes are kept so an escaped delimiter stays literal in
+                // the pattern as well as the replacement.
+                '\\' => match chars.next() {
+                    Some(escaped) => {
+                        parts.last_mut().unwrap().push('\\');
+                        parts.last_mut().unwrap().push(escaped);
+                    }
+                    None => return Err(usage.into()),
+                },
+                c if c == delimiter => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern, replacement, flags] => (pattern, replacement, flags),
+            _ => return Err(usage.into()),
+        };
+        if pattern.is_empty() {
+            return Err("the pattern of a substitution cannot be empty".into());
+        }
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(format!(
+                "the pattern of a substitution cannot be longer than {} characters",
+                MAX_PATTERN_LEN
+            ));
+        }
+
+        let mut global = false;
+        let mut case_insensitive = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                flag => return Err(format!("unknown substitution flag '{}'", flag)),
+            }
+        }
+
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .size_limit(MAX_REGEX_SIZE)
+            .dfa_size_limit(MAX_REGEX_SIZE)
+            .build()
+            .map_err(|err| format!("invalid pattern '{}': {}", pattern, err))?;
+
+        Ok(Substitution {
+            regex,
+            replacement: expand_replacement(replacement),
+            global,
+        })
+    }
+
+    fn apply(&self, description: &str) -> Result<String, String> {
+        if !self.regex.is_match(description) {
+            return Err(format!("'{}' does not match.", self.regex.as_str()));
+        }
+
+        let edited = if self.global {
+            self.regex
+                .replace_all(description, self.replacement.as_str())
+        } else {
+            self.regex.replace(description, self.replacement.as_str())
+        };
+
+        if edited.len() > MAX_DESCRIPTION_LEN {
+            Err(format!(
+                "the result would be longer than {} bytes.",
+                MAX_DESCRIPTION_LEN
+            ))
+        } else if edited.trim().is_empty() {
+            Err("the result would be empty.".into())
+        } else {
+            Ok(edited.trim().into())
+        }
+    }
+}
+
+/// Converts sed's `\N` group references to the `${N}` syntax used by `regex`,
+/// leaving everything else literal.
+fn expand_replacement(replacement: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => expanded.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(group) if group.is_ascii_digit() => {
+                    expanded.push_str("${");
+                    expanded.push(group);
+                    expanded.push('}');
+                }
+                Some('$') => expanded.push_str("$$"),
+                Some(escaped) => expanded.push(escaped),
+                None => expanded.push('\\'),
+            },
+            c => expanded.push(c),
+        }
+    }
+
+    expanded
+}
+
 enum EditOptions<E, F> {
     Must(E, PhantomData<F>),
     MustNot(F, PhantomData<E>),
@@ -167,10 +351,93 @@ where
             if factoid.locked && !is_admin(nick, config) {
                 format!("cannot edit factoid: '{}' because it is locked.", label)
             } else {
+                // Edits keep the factoid's intent, so a script has to still
+                // compile afterwards.
                 let description = editor(&factoid);
+                if factoid.intent == FactoidEnum::Script {
+                    if let Err(err) = script::check(&description) {
+                        return Ok(format!("cannot edit '{}': {}", label, err));
+                    }
+                }
                 db.create_factoid(nick, factoid.intent, &label, &description, false)?;
                 format!("edited factoid: '{}'.", label)
             }
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::{expand_replacement, Substitution, MAX_DESCRIPTION_LEN, MAX_PATTERN_LEN};
+
+    fn substitute(expression: &str, description: &str) -> Result<String, String> {
+        Substitution::parse(expression)?.apply(description)
+    }
+
+    #[test]
+    fn parses_any_punctuation_delimiter() {
+        assert_eq!(substitute("s/a/b/", "aa").unwrap(), "ba");
+        assert_eq!(substitute("s|a|b|", "aa").unwrap(), "ba");
+        assert_eq!(substitute("s#a/b#c/d#", "a/b").unwrap(), "c/d");
+        assert!(Substitution::parse("sxaxbx").is_err());
+        assert!(Substitution::parse("s a b ").is_err());
+        assert!(Substitution::parse("s\\a\\b\\").is_err());
+    }
+
+    #[test]
+    fn escaped_delimiters_are_literal() {
+        assert_eq!(substitute(r"s/a\/b/c\/d/", "a/b").unwrap(), "c/d");
+        assert_eq!(substitute(r"s|a\|b|c|", "a|b").unwrap(), "c");
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(Substitution::parse("").is_err());
+        assert!(Substitution::parse("s/a/b").is_err());
+        assert!(Substitution::parse("s/a/b/g/").is_err());
+        assert!(Substitution::parse("s/a/b/\\").is_err());
+        assert!(Substitution::parse("s/(/b/").is_err());
+    }
+
+    #[test]
+    fn expands_group_references() {
+        assert_eq!(expand_replacement(r"\1-\2"), "${1}-${2}");
+        assert_eq!(expand_replacement("$1"), "$$1");
+        assert_eq!(expand_replacement(r"\$"), "$$");
+        assert_eq!(expand_replacement(r"a\b\"), "ab\\");
+        assert_eq!(
+            substitute(r"s/(\w+) (\w+)/\2 \1/", "hello world").unwrap(),
+            "world hello"
+        );
+        assert_eq!(substitute("s/a/$1/", "a").unwrap(), "$1");
+    }
+
+    #[test]
+    fn applies_flags() {
+        assert_eq!(substitute("s/a/b/", "aAa").unwrap(), "bAa");
+        assert_eq!(substitute("s/a/b/g", "aAa").unwrap(), "bAb");
+        assert_eq!(substitute("s/a/b/i", "Aaa").unwrap(), "baa");
+        assert_eq!(substitute("s/a/b/gi", "aAa").unwrap(), "bbb");
+        assert_eq!(
+            Substitution::parse("s/a/b/x").err().unwrap(),
+            "unknown substitution flag 'x'"
+        );
+    }
+
+    #[test]
+    fn rejects_empty_patterns_and_results() {
+        assert!(Substitution::parse("s//b/").is_err());
+        assert!(substitute("s/a/b/", "c").is_err());
+        assert!(substitute("s/.*//", "abc").is_err());
+    }
+
+    #[test]
+    fn enforces_size_limits() {
+        let pattern = "a".repeat(MAX_PATTERN_LEN + 1);
+        assert!(Substitution::parse(&format!("s/{}/b/", pattern)).is_err());
+        assert!(Substitution::parse("s/a{1000}{1000}/b/").is_err());
+
+        let replacement = "b".repeat(MAX_DESCRIPTION_LEN);
+        assert!(substitute(&format!("s/a/{}/", replacement), "aa").is_err());
+    }
+}
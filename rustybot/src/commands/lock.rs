@@ -1,5 +1,5 @@
 use crate::config::is_admin;
-use crate::config::RustybotSettings;
+use crate::config::Settings;
 use crate::database::Db;
 
 use anyhow::Result;
@@ -8,8 +8,8 @@ use nestor::handler::Command;
 use nestor::request::State;
 
 #[command("lock")]
-pub fn lock(command: &Command, config: State<RustybotSettings>, db: State<Db>) -> Result<String> {
-    if !is_admin(command.source_nick, &config) {
+pub fn lock(command: &Command, config: State<Settings>, db: State<Db>) -> Result<String> {
+    if !is_admin(command.source_nick, &config.get()) {
         return Ok("Only an admin can lock a factoid".into());
     }
 
@@ -37,8 +37,8 @@ pub fn lock(command: &Command, config: State<RustybotSettings>, db: State<Db>) -
 }
 
 #[command("unlock")]
-pub fn unlock(command: &Command, config: State<RustybotSettings>, db: State<Db>) -> Result<String> {
-    if !is_admin(command.source_nick, &config) {
+pub fn unlock(command: &Command, config: State<Settings>, db: State<Db>) -> Result<String> {
+    if !is_admin(command.source_nick, &config.get()) {
         return Ok("Only an admin can unlock a factoid".into());
     }
 
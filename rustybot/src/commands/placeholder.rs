@@ -0,0 +1,298 @@
+use anyhow::Result;
+use nestor::command;
+use nestor::handler::Command;
+use nestor::request::ChannelName;
+use rand::seq::SliceRandom;
+
+/// What placeholders in a factoid description expand to.
+pub struct Context<'a> {
+    pub nick: &'a str,
+    pub who: Option<&'a str>,
+    pub channel: Option<&'a str>,
+    pub arguments: &'a [&'a str],
+}
+
+enum Part {
+    Text(String),
+    Nick,
+    Who,
+    Channel,
+    Argument(usize),
+    AllArguments,
+    Random(Vec<Vec<Part>>),
+}
+
+/// Expands `$nick`, `$who`, `$channel`, `$1`..`$n`, `$*` and
+/// `$random(a|b|c)` in a factoid description. `$$` is a literal `$`, and inside
+/// `$random(...)`, which can be nested, a backslash escapes the next character.
+/// Anything else starting with `$` is left as it is.
+pub fn expand(description: &str, context: &Context) -> String {
+    let mut expanded = String::new();
+    expand_parts(&parse(description), context, &mut expanded);
+    expanded
+}
+
+/// Whether a description refers to the arguments given after the label.
+pub fn takes_arguments(description: &str) -> bool {
+    any_part(&parse(description), &|part| match part {
+        Part::Argument(_) | Part::AllArguments => true,
+        Part::Text(_) | Part::Nick | Part::Who | Part::Channel | Part::Random(_) => false,
+    })
+}
+
+/// Whether a description names the `@ name` target itself.
+pub fn mentions_who(description: &str) -> bool {
+    any_part(&parse(description), &|part| match part {
+        Part::Who => true,
+        Part::Text(_)
+        | Part::Nick
+        | Part::Channel
+        | Part::Argument(_)
+        | Part::AllArguments
+        | Part::Random(_) => false,
+    })
+}
+
+#[command("factoid-preview")]
+pub fn preview(command: &Command, channel: ChannelName) -> Result<String> {
+    if command.arguments.is_empty() {
+        return Ok("Invalid command format, please use ~factoid-preview <description>".into());
+    }
+
+    let context = Context {
+        nick: command.source_nick,
+        who: None,
+        channel: channel.as_str(),
+        arguments: &[],
+    };
+    Ok(expand(&command.arguments.join(" "), &context))
+}
+
+fn expand_parts(parts: &[Part], context: &Context, expanded: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => expanded.push_str(text),
+            Part::Nick => expanded.push_str(context.nick),
+            Part::Who => expanded.push_str(context.who.unwrap_or(context.nick)),
+            Part::Channel => expanded.push_str(context.channel.unwrap_or_default()),
+            Part::Argument(index) => {
+                if let Some(argument) = context.arguments.get(index - 1) {
+                    expanded.push_str(argument);
+                }
+            }
+            Part::AllArguments => expanded.push_str(&context.arguments.join(" ")),
+            Part::Random(options) => {
+                if let Some(option) = options.choose(&mut rand::thread_rng()) {
+                    expand_parts(option, context, expanded);
+                }
+            }
+        }
+    }
+}
+
+fn any_part(parts: &[Part], predicate: &dyn Fn(&Part) -> bool) -> bool {
+    parts.iter().any(|part| match part {
+        Part::Random(options) => options.iter().any(|option| any_part(option, predicate)),
+        part => predicate(part),
+    })
+}
+
+fn parse(description: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = description;
+
+    while let Some(index) = rest.find('$') {
+        text.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        if rest.starts_with('$') {
+            text.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        match placeholder(rest) {
+            Some((part, len)) => {
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(part);
+                rest = &rest[len..];
+            }
+            None => text.push('$'),
+        }
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    parts
+}
+
+/// Parses the placeholder following a `$`, returning it and how many bytes it
+/// used.
+fn placeholder(rest: &str) -> Option<(Part, usize)> {
+    if rest.starts_with('*') {
+        return Some((Part::AllArguments, 1));
+    }
+
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        return match rest[..digits].parse() {
+            Ok(index) if index > 0 => Some((Part::Argument(index), digits)),
+            _ => None,
+        };
+    }
+
+    let letters = rest.len()
+        - rest
+            .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            .len();
+    match &rest[..letters] {
+        "nick" => Some((Part::Nick, letters)),
+        "who" => Some((Part::Who, letters)),
+        "channel" => Some((Part::Channel, letters)),
+        "random" if rest[letters..].starts_with('(') => {
+            let (options, len) = random_options(&rest[letters + 1..])?;
+            Some((Part::Random(options), letters + 1 + len))
+        }
+        _ => None,
+    }
+}
+
+fn random_options(rest: &str) -> Option<(Vec<Vec<Part>>, usize)> {
+    let mut options = Vec::new();
+    let mut option = String::new();
+    // Nested `$random(...)`s are copied into the option as they are, escapes
+    // included, and parsed along with it.
+    let mut depth = 0;
+    let mut chars = rest.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                if depth > 0 {
+                    option.push('\\');
+                }
+                option.push(escaped);
+            }
+            '$' if rest[index + 1..].starts_with('$') => {
+                option.push_str("$$");
+                chars.next();
+            }
+            '$' if rest[index + 1..].starts_with("random(") => {
+                option.push_str("$random(");
+                depth += 1;
+                chars.nth("random(".len() - 1);
+            }
+            '|' | ')' if depth > 0 => {
+                if c == ')' {
+                    depth -= 1;
+                }
+                option.push(c);
+            }
+            '|' => options.push(parse(&std::mem::take(&mut option))),
+            ')' => {
+                options.push(parse(&option));
+                return Some((options, index + 1));
+            }
+            c => option.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{expand, mentions_who, takes_arguments, Context};
+
+    fn context<'a>(arguments: &'a [&'a str]) -> Context<'a> {
+        Context {
+            nick: "alice",
+            who: Some("bob"),
+            channel: Some("#rust"),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn expands_names() {
+        let context = context(&[]);
+        assert_eq!(
+            expand("$nick told $who in $channel", &context),
+            "alice told bob in #rust"
+        );
+
+        let context = Context {
+            who: None,
+            channel: None,
+            ..context
+        };
+        assert_eq!(expand("hi $who, [$channel]", &context), "hi alice, []");
+    }
+
+    #[test]
+    fn expands_arguments() {
+        let context = context(&["one", "two"]);
+        assert_eq!(expand("$2 $1", &context), "two one");
+        assert_eq!(expand("$3.", &context), ".");
+        assert_eq!(expand("all: $*", &context), "all: one two");
+        assert_eq!(expand("$12", &context), "");
+    }
+
+    #[test]
+    fn expands_random() {
+        let context = context(&[]);
+        assert_eq!(expand("$random(only $nick)", &context), "only alice");
+        for _ in 0..16 {
+            let picked = expand("$random(a|b|c)", &context);
+            assert!(["a", "b", "c"].contains(&picked.as_str()), "{}", picked);
+        }
+        assert_eq!(expand(r"$random(a\|b\))", &context), "a|b)");
+    }
+
+    #[test]
+    fn expands_nested_random() {
+        let context = context(&[]);
+        for _ in 0..16 {
+            let picked = expand("<$random(a|$random(b|c)|d)>", &context);
+            assert!(
+                ["<a>", "<b>", "<c>", "<d>"].contains(&picked.as_str()),
+                "{}",
+                picked
+            );
+        }
+        assert_eq!(expand(r"$random($random(x\|y))!", &context), "x|y!");
+    }
+
+    #[test]
+    fn keeps_literal_dollars() {
+        let context = context(&["one"]);
+        assert_eq!(expand("$$1 costs $$$1", &context), "$1 costs $one");
+        assert_eq!(expand("$$random(a|b)", &context), "$random(a|b)");
+        assert_eq!(expand("$random($$|$$)", &context), "$");
+    }
+
+    #[test]
+    fn leaves_malformed_placeholders() {
+        let context = context(&["one"]);
+        assert_eq!(expand("$0 $nickname $ $", &context), "$0 $nickname $ $");
+        assert_eq!(expand("$random(a|b", &context), "$random(a|b");
+        assert_eq!(expand("$random a", &context), "$random a");
+        assert_eq!(expand(r"$random(a\", &context), r"$random(a\");
+    }
+
+    #[test]
+    fn detects_arguments_and_who() {
+        assert!(takes_arguments("hello $1"));
+        assert!(takes_arguments("$random(a|$random(b|$*))"));
+        assert!(!takes_arguments("hello $$1 $nick"));
+        assert!(mentions_who("$random(a|$who)"));
+        assert!(!mentions_who("$nick $$who"));
+    }
+}
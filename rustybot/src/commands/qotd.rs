@@ -9,22 +9,23 @@ use rand::thread_rng;
 
 #[command("qotd")]
 pub fn qotd(command: &Command, db: State<Db>) -> Result<String> {
+    if !command.arguments.is_empty() {
+        return Ok("Invalid command format, please use `~qotd` or `~qotd add <quote>`".to_string());
+    }
+
     let mut rng = thread_rng();
+    Ok(match db.all_quotes()?.choose(&mut rng) {
+        Some(quote) => quote.quote.clone(),
+        None => "I don't have any quotes :(".to_string(),
+    })
+}
 
-    Ok(match command.arguments.len() {
-        0 => {
-            if let Some(quote) = db.all_quotes()?.choose(&mut rng) {
-                quote.quote.clone()
-            } else {
-                "I don't have any quotes :(".to_string()
-            }
-        }
-        _ if command.arguments[0] == "add" => {
-            let quote = command.arguments[1..].join(" ");
-            db.create_quote(&quote)?;
+#[command("qotd add")]
+pub fn qotd_add(command: &Command, db: State<Db>) -> Result<String> {
+    if command.arguments.is_empty() {
+        return Ok("Invalid command format, please use `~qotd add <quote>`".to_string());
+    }
 
-            "Added new quote".to_string()
-        }
-        _ => "Invalid command format, please use `~qotd` or `~qotd add <quote>`".to_string(),
-    })
+    db.create_quote(&command.arguments.join(" "))?;
+    Ok("Added new quote".to_string())
 }
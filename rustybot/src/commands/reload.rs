@@ -0,0 +1,18 @@
+use crate::config::{is_admin, Settings};
+
+use nestor::command;
+use nestor::handler::Command;
+use nestor::request::State;
+use nestor::Reloader;
+
+#[command("reload")]
+pub fn reload(command: &Command, config: State<Settings>, reloader: State<Reloader>) -> String {
+    if !is_admin(command.source_nick, &config.get()) {
+        return "Only an admin can reload the configuration".into();
+    }
+
+    match reloader.reload() {
+        Ok(report) => report.to_string(),
+        Err(err) => format!("failed to reload configuration: {}", err),
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use nestor::command;
 use nestor::handler::Command;
 
-#[command("error")]
+#[command("error", aliases = ["e"])]
 pub fn rustc_error(command: &Command) -> Result<String> {
     if command.arguments.len() != 1 {
         return Ok("Invalid command format, please use ~error <error code>".into());
@@ -0,0 +1,102 @@
+use crate::database::Db;
+
+use anyhow::Result;
+use nestor::command;
+use nestor::handler::Command;
+use nestor::request::State;
+
+const PAGE_SIZE: i64 = 10;
+
+#[command("search")]
+pub fn search(command: &Command, db: State<Db>) -> Result<String> {
+    search_helper(command, "search", |text, offset, limit| {
+        db.search_factoid_labels(text, offset, limit)
+    })
+}
+
+#[command("search-content")]
+pub fn search_content(command: &Command, db: State<Db>) -> Result<String> {
+    search_helper(command, "search-content", |text, offset, limit| {
+        db.search_factoid_contents(text, offset, limit)
+    })
+}
+
+fn search_helper<F>(command: &Command, name: &str, search: F) -> Result<String>
+where
+    F: Fn(&str, i64, i64) -> Result<Vec<String>>,
+{
+    // A trailing number selects the page, e.g. `~search rust 2`.
+    let (text, page): (String, i64) = match command.arguments.split_last() {
+        Some((page, words)) if !words.is_empty() && page.parse::<i64>().is_ok() => {
+            (words.join(" "), page.parse().unwrap())
+        }
+        _ => (command.arguments.join(" "), 1),
+    };
+
+    let usage = format!("Invalid command format, please use ~{} <text> [page]", name);
+    if text.trim().is_empty() || page < 1 {
+        return Ok(usage);
+    }
+    let offset = match (page - 1).checked_mul(PAGE_SIZE) {
+        Some(offset) => offset,
+        None => return Ok(usage),
+    };
+
+    // One extra result tells us whether there is another page.
+    let mut labels = search(&text, offset, PAGE_SIZE + 1)?;
+    if labels.is_empty() {
+        return Ok(format!("No factoids match '{}'", text));
+    }
+
+    let more = labels.len() as i64 > PAGE_SIZE;
+    labels.truncate(PAGE_SIZE as usize);
+
+    Ok(format!(
+        "Factoids matching '{}' (page {}): {}{}",
+        text,
+        page,
+        labels.join(", "),
+        if more {
+            format!(" (more with ~{} {} {})", name, text, page + 1)
+        } else {
+            String::new()
+        }
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{search_helper, PAGE_SIZE};
+    use nestor::handler::Command;
+
+    fn search(command_str: &str) -> String {
+        let command = Command::from_command_str("user", command_str).unwrap();
+        search_helper(&command, "search", |text, offset, limit| {
+            assert_eq!(limit, PAGE_SIZE + 1);
+            Ok((offset..offset + 2)
+                .map(|n| format!("{}{}", text, n))
+                .collect())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn pages_through_results() {
+        assert_eq!(
+            search("search foo"),
+            "Factoids matching 'foo' (page 1): foo0, foo1"
+        );
+        assert_eq!(
+            search("search foo 3"),
+            "Factoids matching 'foo' (page 3): foo20, foo21"
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_pages() {
+        let usage = "Invalid command format, please use ~search <text> [page]";
+        assert_eq!(search("search foo 0"), usage);
+        assert_eq!(search("search foo -1"), usage);
+        assert_eq!(search("search foo 9223372036854775807"), usage);
+    }
+}
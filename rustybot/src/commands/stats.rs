@@ -0,0 +1,78 @@
+use super::duration::{parse_duration, since};
+use crate::database::Db;
+
+use anyhow::Result;
+use nestor::command;
+use nestor::handler::Command;
+use nestor::request::State;
+
+const DEFAULT_TOP: i64 = 10;
+const MAX_TOP: i64 = 25;
+
+#[command("factoid-stats")]
+pub fn factoid_stats(command: &Command, db: State<Db>) -> Result<String> {
+    if command.arguments.is_empty() {
+        return Ok("Invalid command format, please use ~factoid-stats <factoid>".into());
+    }
+
+    let label = command.arguments.join(" ");
+    if db.get_factoid(&label)?.is_none() {
+        return Ok(format!("Factoid '{}' does not exist", label));
+    }
+
+    let stats = db.factoid_stats(&label)?;
+    Ok(match stats.last_hit {
+        Some(last_hit) => format!(
+            "Factoid '{}' has been used {} time(s) by {} user(s), last on {}",
+            label,
+            stats.hits,
+            stats.users,
+            last_hit.format("%Y-%m-%d %H:%M")
+        ),
+        None => format!("Factoid '{}' has never been used", label),
+    })
+}
+
+#[command("top-factoids")]
+pub fn top_factoids(command: &Command, db: State<Db>) -> Result<String> {
+    let usage = "Invalid command format, please use ~top-factoids [count] [period]";
+    let mut count = DEFAULT_TOP;
+    let mut period = None;
+    for argument in &command.arguments {
+        if let Ok(number) = argument.parse::<i64>() {
+            count = number;
+        } else if let Some(duration) = parse_duration(argument) {
+            period = Some((argument, duration));
+        } else {
+            return Ok(usage.into());
+        }
+    }
+
+    if !(1..=MAX_TOP).contains(&count) {
+        return Ok(format!("The count has to be between 1 and {}", MAX_TOP));
+    }
+
+    let cutoff = match period {
+        Some((_, duration)) => match since(duration) {
+            Some(cutoff) => Some(cutoff),
+            None => return Ok(usage.into()),
+        },
+        None => None,
+    };
+
+    let top = db.top_factoids(count, cutoff)?;
+    let during = match period {
+        Some((text, _)) => format!(" in the last {}", text),
+        None => String::new(),
+    };
+
+    Ok(if top.is_empty() {
+        format!("No factoids have been used{}", during)
+    } else {
+        let entries: Vec<_> = top
+            .iter()
+            .map(|factoid| format!("{} ({})", factoid.label, factoid.hits))
+            .collect();
+        format!("Most used factoids{}: {}", during, entries.join(", "))
+    })
+}
@@ -1,7 +1,15 @@
-use std::fs;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::commands::duration::{parse_duration, since};
+
+use anyhow::anyhow;
+use nestor::config::{ConfigSource, Reloadable};
+use nestor::plugin::PluginConfig;
+use nestor::wasm::WasmSettings;
 use nestor::Error;
 use serde::Deserialize;
 use structopt::StructOpt;
@@ -35,6 +43,23 @@ pub enum Command {
     },
     #[structopt(name = "launch")]
     Launch {},
+    #[structopt(name = "report")]
+    Report {
+        #[structopt(long = "top", default_value = "20")]
+        top: i64,
+        #[structopt(long = "period", parse(try_from_str = parse_period))]
+        period: Option<Duration>,
+        #[structopt(long = "stale", default_value = "90d", parse(try_from_str = parse_period))]
+        stale: Duration,
+    },
+}
+
+fn parse_period(period: &str) -> Result<Duration, String> {
+    let duration = parse_duration(period).ok_or_else(|| format!("invalid period '{}'", period))?;
+    match since(duration) {
+        Some(_) => Ok(duration),
+        None => Err(format!("period '{}' is too long", period)),
+    }
 }
 
 #[derive(StructOpt)]
@@ -51,17 +76,52 @@ pub enum ImportType {
 
 #[derive(Deserialize)]
 pub struct Config {
-    #[serde(rename = "connection")]
-    pub nestor: nestor::config::Config,
+    pub connection: Option<nestor::config::Config>,
+    #[serde(default)]
+    pub networks: BTreeMap<String, nestor::config::Config>,
     pub rustybot: RustybotSettings,
+    #[serde(default)]
+    pub plugins: Plugins,
+    pub wasm: Option<WasmSettings>,
 }
 
+#[derive(Deserialize, Default)]
+pub struct Plugins {
+    #[serde(default)]
+    pub factoids: PluginConfig<()>,
+    #[serde(default)]
+    pub windows: PluginConfig<()>,
+    pub github: Option<PluginConfig<GithubAuth>>,
+}
+
+pub type Settings = Reloadable<RustybotSettings>;
+pub type Networks = Vec<(String, nestor::config::Config)>;
+
 #[derive(Deserialize)]
 pub struct RustybotSettings {
     pub admins: Vec<String>,
     pub database_url: String,
     pub contact: String,
-    pub github_auth: GithubAuth,
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+    #[serde(default)]
+    pub scripts: ScriptSettings,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ScriptSettings {
+    pub max_operations: u64,
+    pub timeout_millis: u64,
+}
+
+impl Default for ScriptSettings {
+    fn default() -> Self {
+        ScriptSettings {
+            max_operations: 100_000,
+            timeout_millis: 250,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -72,10 +132,40 @@ pub struct GithubAuth {
 
 impl Config {
     pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        // Load entries via serde
-        let conf = fs::read_to_string(path.as_ref())?;
-        let conf: Config = toml::de::from_str(&conf)?;
-        Ok(conf)
+        ConfigSource::file(path)?
+            .secrets("RUSTYBOT_SECRETS_FILE")?
+            .env("RUSTYBOT_NICK_PASSWORD", "connection.nick_password")
+            .env("RUSTYBOT_SERVER_PASSWORD", "connection.password")
+            .env("RUSTYBOT_DATABASE_URL", "rustybot.database_url")
+            .env("RUSTYBOT_GITHUB_USERNAME", "plugins.github.username")
+            .env("RUSTYBOT_GITHUB_TOKEN", "plugins.github.password")
+            .deserialize()
+    }
+
+    pub fn into_parts(
+        self,
+    ) -> Result<(Networks, RustybotSettings, Plugins, Option<WasmSettings>), Error> {
+        let mut networks: Vec<_> = self.networks.into_iter().collect();
+        if let Some(connection) = self.connection {
+            if networks.iter().any(|(name, _)| name == "default") {
+                return Err(anyhow!(
+                    "[connection] conflicts with a network named 'default'"
+                ));
+            }
+            networks.insert(0, ("default".into(), connection));
+        }
+
+        if networks.is_empty() {
+            return Err(anyhow!(
+                "no [connection] or [networks.<name>] section configured"
+            ));
+        }
+
+        for (_, network) in &networks {
+            network.validate()?;
+        }
+
+        Ok((networks, self.rustybot, self.plugins, self.wasm))
     }
 }
 
@@ -1,13 +1,21 @@
 use self::import_models::RFactoid;
 use self::models::{
-    Factoid, FactoidEnum, NewFactoid, NewQuote, NewWinError, Quote, WinError, WinErrorVariant,
+    Factoid, FactoidEnum, FactoidHits, FactoidLabel, FactoidStats, Ignore, NewFactoid,
+    NewFactoidHit, NewIgnore, NewPluginValue, NewQuote, NewWinError, Quote, WinError,
+    WinErrorVariant,
 };
 
+use std::ops::RangeInclusive;
+
 use anyhow::Result;
+use chrono::naive::NaiveDateTime;
 use chrono::offset::Utc;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::sqlite::SqliteConnection;
+use nestor::config::CaseMapping;
+use nestor::ignore::{IgnoreRule, IgnoreStore};
+use nestor::wasm::KvStore;
 
 pub mod import_models;
 pub mod models;
@@ -15,6 +23,7 @@ pub mod schema;
 
 embed_migrations!();
 
+#[derive(Clone)]
 pub struct Db {
     pool: Pool<ConnectionManager<SqliteConnection>>,
 }
@@ -34,12 +43,233 @@ impl Db {
         let connection = self.pool.get()?;
         factoids
             .filter(label.eq(key))
-            .order(timestamp.desc())
+            .order((timestamp.desc(), id.desc()))
             .first::<Factoid>(&connection)
             .optional()
             .map_err(From::from)
     }
 
+    /// Looks up a factoid like `get_factoid`, falling back to a label that
+    /// only differs from `key` in case under `casemapping`.
+    pub fn find_factoid(&self, key: &str, casemapping: CaseMapping) -> Result<Option<Factoid>> {
+        use self::schema::factoids::dsl::*;
+
+        if let Some(factoid) = self.get_factoid(key)? {
+            return Ok(Some(factoid));
+        }
+
+        // LIKE only folds ASCII letters, so the characters some casemappings
+        // fold as well match anything here and are compared afterwards.
+        let pattern: String = key
+            .chars()
+            .map(|c| match c {
+                '%' | '_' => format!("\\{}", c),
+                '[' | ']' | '\\' | '^' | '{' | '}' | '|' | '~' => "_".into(),
+                c => c.to_string(),
+            })
+            .collect();
+
+        let connection = self.pool.get()?;
+        let candidates = factoids
+            .filter(label.like(pattern).escape('\\'))
+            .order((timestamp.desc(), id.desc()))
+            .load::<Factoid>(&connection)?;
+
+        Ok(candidates
+            .into_iter()
+            .find(|factoid| casemapping.eq(&factoid.label, key)))
+    }
+
+    /// Counts every revision of a factoid, including the ones that forget it.
+    pub fn factoid_revision_count(&self, key: &str) -> Result<i64> {
+        use self::schema::factoids::dsl::*;
+
+        let connection = self.pool.get()?;
+        factoids
+            .filter(label.eq(key))
+            .count()
+            .get_result(&connection)
+            .map_err(From::from)
+    }
+
+    /// Loads up to `limit` revisions of a factoid, newest first, skipping the
+    /// `offset` newest ones.
+    pub fn factoid_history(&self, key: &str, offset: i64, limit: i64) -> Result<Vec<Factoid>> {
+        use self::schema::factoids::dsl::*;
+
+        let connection = self.pool.get()?;
+        factoids
+            .filter(label.eq(key))
+            .order((timestamp.desc(), id.desc()))
+            .offset(offset)
+            .limit(limit)
+            .load::<Factoid>(&connection)
+            .map_err(From::from)
+    }
+
+    /// Loads a single revision of a factoid. Revisions are numbered from 1,
+    /// oldest first.
+    pub fn factoid_revision(&self, key: &str, revision: i64) -> Result<Option<Factoid>> {
+        use self::schema::factoids::dsl::*;
+
+        if revision < 1 {
+            return Ok(None);
+        }
+
+        let connection = self.pool.get()?;
+        factoids
+            .filter(label.eq(key))
+            .order((timestamp.asc(), id.asc()))
+            .offset(revision - 1)
+            .first::<Factoid>(&connection)
+            .optional()
+            .map_err(From::from)
+    }
+
+    /// Lists the factoids whose label is within `lengths` characters long.
+    pub fn factoid_labels(&self, lengths: RangeInclusive<usize>) -> Result<Vec<String>> {
+        use diesel::sql_types::BigInt;
+
+        let connection = self.pool.get()?;
+        let labels = diesel::sql_query(
+            "SELECT f.label FROM factoids f \
+             WHERE length(f.label) BETWEEN ? AND ? \
+             AND f.id = (SELECT id FROM factoids WHERE label = f.label ORDER BY timestamp DESC, id DESC LIMIT 1) \
+             AND f.intent != 'forget'",
+        )
+        .bind::<BigInt, _>(*lengths.start() as i64)
+        .bind::<BigInt, _>(*lengths.end() as i64)
+        .load::<FactoidLabel>(&connection)?;
+
+        Ok(labels.into_iter().map(|factoid| factoid.label).collect())
+    }
+
+    /// Finds factoids whose label matches every word of `text`, best match first.
+    pub fn search_factoid_labels(
+        &self,
+        text: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        self.search_factoids("label", text, offset, limit)
+    }
+
+    /// Finds factoids whose description matches every word of `text`, best
+    /// match first.
+    pub fn search_factoid_contents(
+        &self,
+        text: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        self.search_factoids("description", text, offset, limit)
+    }
+
+    fn search_factoids(
+        &self,
+        column: &str,
+        text: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        use diesel::sql_types::{BigInt, Text};
+
+        // Every word is quoted so user input is never parsed as FTS5 syntax,
+        // and matched as a prefix.
+        let query = text
+            .split_whitespace()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .map(|word| format!("{} : \"{}\"*", column, word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let connection = self.pool.get()?;
+        let labels = diesel::sql_query(
+            "SELECT label FROM factoid_search WHERE factoid_search MATCH ? \
+             ORDER BY rank LIMIT ? OFFSET ?",
+        )
+        .bind::<Text, _>(query)
+        .bind::<BigInt, _>(limit)
+        .bind::<BigInt, _>(offset)
+        .load::<FactoidLabel>(&connection)?;
+
+        Ok(labels.into_iter().map(|factoid| factoid.label).collect())
+    }
+
+    pub fn record_factoid_hit(&self, key: &str, nick: &str, channel: Option<&str>) -> Result<()> {
+        use self::schema::factoid_hits;
+
+        let connection = self.pool.get()?;
+        let hit = NewFactoidHit {
+            label: key,
+            nickname: nick,
+            channel,
+            timestamp: Utc::now().naive_utc(),
+        };
+
+        diesel::insert_into(factoid_hits::table)
+            .values(&hit)
+            .execute(&connection)?;
+
+        Ok(())
+    }
+
+    pub fn factoid_stats(&self, key: &str) -> Result<FactoidStats> {
+        use diesel::sql_types::Text;
+
+        let connection = self.pool.get()?;
+        diesel::sql_query(
+            "SELECT COUNT(*) AS hits, COUNT(DISTINCT nickname) AS users, MAX(timestamp) AS last_hit \
+             FROM factoid_hits WHERE label = ?",
+        )
+        .bind::<Text, _>(key)
+        .get_result(&connection)
+        .map_err(From::from)
+    }
+
+    /// Lists the most used factoids, counting only hits after `since` if given.
+    pub fn top_factoids(
+        &self,
+        limit: i64,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<FactoidHits>> {
+        use diesel::sql_types::{BigInt, Nullable, Timestamp};
+
+        let connection = self.pool.get()?;
+        diesel::sql_query(
+            "SELECT label, COUNT(*) AS hits FROM factoid_hits \
+             WHERE ? IS NULL OR timestamp >= ? \
+             GROUP BY label ORDER BY hits DESC, label LIMIT ?",
+        )
+        .bind::<Nullable<Timestamp>, _>(since)
+        .bind::<Nullable<Timestamp>, _>(since)
+        .bind::<BigInt, _>(limit)
+        .load(&connection)
+        .map_err(From::from)
+    }
+
+    /// Lists the factoids that have been neither used nor edited since `since`.
+    pub fn stale_factoids(&self, since: NaiveDateTime) -> Result<Vec<String>> {
+        use diesel::sql_types::Timestamp;
+
+        let connection = self.pool.get()?;
+        let labels = diesel::sql_query(
+            "SELECT f.label FROM factoids f \
+             WHERE f.id = (SELECT id FROM factoids WHERE label = f.label ORDER BY timestamp DESC, id DESC LIMIT 1) \
+             AND f.intent != 'forget' AND f.timestamp < ? \
+             AND NOT EXISTS (SELECT 1 FROM factoid_hits h WHERE h.label = f.label AND h.timestamp >= ?) \
+             ORDER BY f.label",
+        )
+        .bind::<Timestamp, _>(since)
+        .bind::<Timestamp, _>(since)
+        .load::<FactoidLabel>(&connection)?;
+
+        Ok(labels.into_iter().map(|factoid| factoid.label).collect())
+    }
+
     pub fn all_factoids(&self) -> Result<Vec<Factoid>> {
         use self::schema::factoids;
 
@@ -164,3 +394,100 @@ impl Db {
         Ok(())
     }
 }
+
+impl IgnoreStore for Db {
+    fn load(&self) -> Result<Vec<IgnoreRule>> {
+        use self::schema::ignores;
+
+        let connection = self.pool.get()?;
+        let rules = ignores::table
+            .load::<Ignore>(&connection)?
+            .into_iter()
+            .map(|ignore| IgnoreRule {
+                mask: ignore.mask,
+                expires_at: ignore.expires_at.map(|expires_at| expires_at as u64),
+                reason: ignore.reason,
+                added_by: ignore.added_by,
+            })
+            .collect();
+
+        Ok(rules)
+    }
+
+    fn save(&self, rules: &[IgnoreRule]) -> Result<()> {
+        use self::schema::ignores;
+
+        let connection = self.pool.get()?;
+        connection.transaction(|| {
+            diesel::delete(ignores::table).execute(&connection)?;
+
+            for rule in rules {
+                let new_ignore = NewIgnore {
+                    mask: &rule.mask,
+                    expires_at: rule.expires_at.map(|expires_at| expires_at as i64),
+                    reason: rule.reason.as_deref(),
+                    added_by: &rule.added_by,
+                };
+
+                diesel::insert_into(ignores::table)
+                    .values(&new_ignore)
+                    .execute(&connection)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl KvStore for Db {
+    fn get(&self, plugin: &str, name: &str) -> Result<Option<String>> {
+        use self::schema::plugin_values::dsl::*;
+
+        let connection = self.pool.get()?;
+        plugin_values
+            .filter(namespace.eq(plugin))
+            .filter(key.eq(name))
+            .select(value)
+            .first::<String>(&connection)
+            .optional()
+            .map_err(From::from)
+    }
+
+    fn set(&self, plugin: &str, name: &str, new_value: Option<&str>) -> Result<()> {
+        use self::schema::plugin_values::dsl::*;
+
+        let connection = self.pool.get()?;
+        match new_value {
+            Some(new_value) => {
+                diesel::replace_into(plugin_values)
+                    .values(&NewPluginValue {
+                        namespace: plugin,
+                        key: name,
+                        value: new_value,
+                    })
+                    .execute(&connection)?;
+            }
+            None => {
+                diesel::delete(
+                    plugin_values
+                        .filter(namespace.eq(plugin))
+                        .filter(key.eq(name)),
+                )
+                .execute(&connection)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count(&self, plugin: &str) -> Result<usize> {
+        use self::schema::plugin_values::dsl::*;
+
+        let connection = self.pool.get()?;
+        let keys = plugin_values
+            .filter(namespace.eq(plugin))
+            .count()
+            .get_result::<i64>(&connection)?;
+        Ok(keys as usize)
+    }
+}
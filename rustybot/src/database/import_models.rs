@@ -39,6 +39,7 @@ impl From<Factoid> for RFactoid {
             FactoidEnum::Act => Some("act".into()),
             FactoidEnum::Say => Some("say".into()),
             FactoidEnum::Alias => Some("alias".into()),
+            FactoidEnum::Script => Some("script".into()),
             FactoidEnum::Forget => None,
         };
 
@@ -2,7 +2,10 @@ use std::io::Write;
 use std::str::FromStr;
 
 use super::import_models::RFactoid;
+use super::schema::factoid_hits;
 use super::schema::factoids;
+use super::schema::ignores;
+use super::schema::plugin_values;
 use super::schema::qotd;
 use super::schema::winerrors;
 
@@ -11,7 +14,7 @@ use chrono::naive::NaiveDateTime;
 use diesel::backend::Backend;
 use diesel::deserialize::{self, FromSql};
 use diesel::serialize::{self, Output, ToSql};
-use diesel::sql_types::Text;
+use diesel::sql_types::{BigInt, Nullable, Text, Timestamp};
 use diesel::sqlite::Sqlite;
 use nestor::response::Response;
 
@@ -26,6 +29,12 @@ pub struct Factoid {
     pub locked: bool,
 }
 
+#[derive(QueryableByName)]
+pub struct FactoidLabel {
+    #[sql_type = "Text"]
+    pub label: String,
+}
+
 #[derive(Insertable)]
 #[table_name = "factoids"]
 pub struct NewFactoid<'a> {
@@ -37,6 +46,33 @@ pub struct NewFactoid<'a> {
     pub locked: bool,
 }
 
+#[derive(Insertable)]
+#[table_name = "factoid_hits"]
+pub struct NewFactoidHit<'a> {
+    pub label: &'a str,
+    pub nickname: &'a str,
+    pub channel: Option<&'a str>,
+    pub timestamp: NaiveDateTime,
+}
+
+#[derive(QueryableByName)]
+pub struct FactoidStats {
+    #[sql_type = "BigInt"]
+    pub hits: i64,
+    #[sql_type = "BigInt"]
+    pub users: i64,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub last_hit: Option<NaiveDateTime>,
+}
+
+#[derive(QueryableByName)]
+pub struct FactoidHits {
+    #[sql_type = "Text"]
+    pub label: String,
+    #[sql_type = "BigInt"]
+    pub hits: i64,
+}
+
 #[derive(Queryable)]
 pub struct Quote {
     pub id: i32,
@@ -49,6 +85,32 @@ pub struct NewQuote<'a> {
     pub quote: &'a str,
 }
 
+#[derive(Queryable)]
+pub struct Ignore {
+    pub id: i32,
+    pub mask: String,
+    pub expires_at: Option<i64>,
+    pub reason: Option<String>,
+    pub added_by: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "ignores"]
+pub struct NewIgnore<'a> {
+    pub mask: &'a str,
+    pub expires_at: Option<i64>,
+    pub reason: Option<&'a str>,
+    pub added_by: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "plugin_values"]
+pub struct NewPluginValue<'a> {
+    pub namespace: &'a str,
+    pub key: &'a str,
+    pub value: &'a str,
+}
+
 #[derive(Queryable)]
 pub struct WinError {
     pub id: i32,
@@ -92,6 +154,7 @@ pub enum FactoidEnum {
     Say,
     Alias,
     Forget,
+    Script,
 }
 
 #[derive(Debug, PartialEq, FromSqlRow, AsExpression, Clone, Copy)]
@@ -119,6 +182,7 @@ impl ToSql<Text, Sqlite> for FactoidEnum {
             FactoidEnum::Say => "say",
             FactoidEnum::Alias => "alias",
             FactoidEnum::Forget => "forget",
+            FactoidEnum::Script => "script",
         };
 
         <str as ToSql<Text, Sqlite>>::to_sql(write, out)
@@ -133,6 +197,7 @@ impl FromSql<Text, Sqlite> for FactoidEnum {
             "say" => Ok(FactoidEnum::Say),
             "alias" => Ok(FactoidEnum::Alias),
             "forget" => Ok(FactoidEnum::Forget),
+            "script" => Ok(FactoidEnum::Script),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
@@ -145,6 +210,7 @@ impl ToString for FactoidEnum {
             FactoidEnum::Say => "say",
             FactoidEnum::Alias => "alias",
             FactoidEnum::Forget => "forget",
+            FactoidEnum::Script => "script",
         }
         .into()
     }
@@ -158,6 +224,7 @@ impl FromStr for FactoidEnum {
             "say" => Ok(FactoidEnum::Say),
             "alias" => Ok(FactoidEnum::Alias),
             "forget" => Ok(FactoidEnum::Forget),
+            "script" => Ok(FactoidEnum::Script),
             _ => Err(anyhow!("Unrecognized enum variant")),
         }
     }
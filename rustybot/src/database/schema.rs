@@ -1,3 +1,13 @@
+table! {
+    factoid_hits (id) {
+        id -> Integer,
+        label -> Text,
+        nickname -> Text,
+        channel -> Nullable<Text>,
+        timestamp -> Timestamp,
+    }
+}
+
 table! {
     factoids (id) {
         id -> Integer,
@@ -10,6 +20,25 @@ table! {
     }
 }
 
+table! {
+    ignores (id) {
+        id -> Integer,
+        mask -> Text,
+        expires_at -> Nullable<BigInt>,
+        reason -> Nullable<Text>,
+        added_by -> Text,
+    }
+}
+
+table! {
+    plugin_values (id) {
+        id -> Integer,
+        namespace -> Text,
+        key -> Text,
+        value -> Text,
+    }
+}
+
 table! {
     qotd (id) {
         id -> Integer,
@@ -27,4 +56,11 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(factoids, qotd, winerrors,);
+allow_tables_to_appear_in_same_query!(
+    factoid_hits,
+    factoids,
+    ignores,
+    plugin_values,
+    qotd,
+    winerrors,
+);
@@ -14,20 +14,21 @@ use crate::config::{Args, Command, Config, ImportType};
 use crate::database::import_models::{RFactoid, WinError};
 use crate::database::models::WinErrorVariant;
 
+use nestor::config::Reloadable;
 use nestor::Nestor;
 use structopt::StructOpt;
 
 mod commands;
 mod config;
 mod database;
+mod script;
 
 fn main() {
     let args = Args::from_args();
 
-    let Config {
-        nestor: nestor_config,
-        rustybot: config,
-    } = Config::load(args.config).unwrap();
+    let (networks, config, plugins, wasm) = Config::load(&args.config)
+        .and_then(Config::into_parts)
+        .unwrap();
     let db = database::Db::open(&config.database_url).unwrap();
 
     match args.command {
@@ -108,11 +109,56 @@ fn main() {
             }
         }
 
+        Command::Report { top, period, stale } => {
+            // Both periods were checked to be representable when parsed.
+            let since = period.and_then(commands::duration::since);
+            println!("Most used factoids:");
+            for factoid in db.top_factoids(top, since).unwrap() {
+                println!("{:>8} {}", factoid.hits, factoid.label);
+            }
+
+            let stale_since = commands::duration::since(stale).expect("stale period too long");
+            println!();
+            println!(
+                "Factoids not used or edited since {}:",
+                stale_since.format("%Y-%m-%d")
+            );
+            for label in db.stale_factoids(stale_since).unwrap() {
+                println!("{}", label);
+            }
+        }
+
         Command::Launch {} => {
-            Nestor::with_config(nestor_config)
-                .manage(db)
-                .manage(config)
-                .activate();
+            let metrics_address = config.metrics_address;
+            let settings = Reloadable::new(config);
+            let reloaded_settings = settings.clone();
+            let path = args.config;
+
+            let mut nestor = Nestor::with_networks(networks)
+                .reload_all_with(move || {
+                    let (networks, rustybot, _, _) = Config::load(&path)?.into_parts()?;
+                    let settings = reloaded_settings.clone();
+                    Ok((networks, move || {
+                        settings.set(rustybot);
+                    }))
+                })
+                .mount(commands::routes())
+                .plugin(commands::Factoids, plugins.factoids)
+                .plugin(commands::WindowsErrors, plugins.windows)
+                .ignore_store(db.clone())
+                .manage(db.clone())
+                .manage(settings);
+            if let Some(github) = plugins.github {
+                nestor = nestor.plugin(commands::Github, github);
+            }
+            if let Some(wasm) = wasm {
+                nestor = nestor.wasm_plugins(wasm, db);
+            }
+            if let Some(addr) = metrics_address {
+                nestor = nestor.metrics_endpoint(addr);
+            }
+
+            nestor.activate();
         }
     }
 }
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use crate::config::ScriptSettings;
+use crate::database::models::FactoidEnum;
+use crate::database::Db;
+
+use anyhow::{anyhow, Result};
+use rand::distributions::Uniform;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rhai::{Array, Dynamic, Engine, Scope};
+use tokio::task;
+
+const MAX_STRING_SIZE: usize = 1024;
+const MAX_COLLECTION_SIZE: usize = 256;
+
+pub struct Context {
+    pub nick: String,
+    pub channel: Option<String>,
+    pub arguments: Vec<String>,
+}
+
+/// An engine without `eval`, so scripts can't build code the checks at
+/// learn time never saw.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.disable_symbol("eval");
+    engine
+}
+
+pub fn check(source: &str) -> Result<()> {
+    engine()
+        .compile(source)
+        .map(|_| ())
+        .map_err(|err| anyhow!("{}", err))
+}
+
+/// Runs a script on the blocking pool, since a script within its limits can
+/// still take long enough to hold up the other handlers on a worker thread.
+pub async fn run_blocking(
+    source: String,
+    context: Context,
+    db: Db,
+    settings: ScriptSettings,
+) -> Result<String> {
+    task::spawn_blocking(move || run(&source, &context, &db, &settings)).await?
+}
+
+pub fn run(source: &str, context: &Context, db: &Db, settings: &ScriptSettings) -> Result<String> {
+    let mut engine = engine();
+    engine
+        .set_max_operations(settings.max_operations)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        .set_max_call_levels(16)
+        .set_max_expr_depths(32, 32);
+
+    let started = Instant::now();
+    let timeout = Duration::from_millis(settings.timeout_millis);
+    // Scripts only talk back through their result, never on the bot's stdout.
+    engine.on_print(|_| {});
+    engine.on_debug(|_, _, _| {});
+    engine.on_progress(move |_| {
+        if started.elapsed() > timeout {
+            Some("script timed out".into())
+        } else {
+            None
+        }
+    });
+
+    engine.register_fn("pick", |items: Array| {
+        items
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("random", |low: i64, high: i64| {
+        if low >= high {
+            low
+        } else {
+            rand::thread_rng().sample(Uniform::new_inclusive(low, high))
+        }
+    });
+    let db = db.clone();
+    engine.register_fn("factoid", move |label: &str| match db.get_factoid(label) {
+        Ok(Some(factoid)) if factoid.intent == FactoidEnum::Say => factoid.description.into(),
+        Ok(Some(factoid)) if factoid.intent == FactoidEnum::Act => factoid.description.into(),
+        _ => Dynamic::UNIT,
+    });
+
+    let mut scope = Scope::new();
+    scope.push_constant("nick", context.nick.clone());
+    scope.push_constant(
+        "channel",
+        context.channel.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    scope.push_constant(
+        "args",
+        context
+            .arguments
+            .iter()
+            .cloned()
+            .map(Dynamic::from)
+            .collect::<Array>(),
+    );
+
+    let result = engine
+        .eval_with_scope::<Dynamic>(&mut scope, source)
+        .map_err(|err| anyhow!("{}", err))?;
+
+    Ok(if result.is_unit() {
+        String::new()
+    } else {
+        result.to_string()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, run, Context};
+    use crate::config::ScriptSettings;
+    use crate::database::Db;
+
+    fn eval(source: &str) -> anyhow::Result<String> {
+        let db = Db::open(":memory:").unwrap();
+        let context = Context {
+            nick: "alice".into(),
+            channel: Some("#rust".into()),
+            arguments: vec!["4".into()],
+        };
+        let settings = ScriptSettings {
+            max_operations: 1000,
+            timeout_millis: 60_000,
+        };
+        run(source, &context, &db, &settings)
+    }
+
+    #[test]
+    fn runs_scripts() {
+        assert_eq!(
+            eval(r#"nick + " rolled " + args[0] + " in " + channel"#).unwrap(),
+            "alice rolled 4 in #rust"
+        );
+        assert_eq!(eval("let x = random(3, 3); x").unwrap(), "3");
+        assert_eq!(eval("()").unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_scripts_that_do_not_compile() {
+        assert!(check("1 +").is_err());
+        assert!(check(r#"eval("1 + 1")"#).is_err());
+        assert!(eval(r#"eval("1 + 1")"#).is_err());
+        assert!(check(r#""rolls " + random(1, 6)"#).is_ok());
+    }
+
+    #[test]
+    fn stops_at_the_operation_limit() {
+        assert!(eval("let x = 0; loop { x += 1; }").is_err());
+        assert!(eval("let x = 0; while x < 10 { x += 1; } x").is_ok());
+    }
+
+    #[test]
+    fn output_only_comes_from_the_result() {
+        assert_eq!(
+            eval(r#"print("hi"); debug("there"); "done""#).unwrap(),
+            "done"
+        );
+    }
+}